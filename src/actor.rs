@@ -1,17 +1,26 @@
-use std::process;
-
 use colored::Colorize;
 use crossterm::execute;
 use crossterm::style::Print;
-use inquire::Select;
+use inquire::{Confirm, Select};
+use serde_json::json;
+use std::io::IsTerminal;
 
-use crate::cli::Options;
-use crate::{git, openai, util};
+use crate::cli::{Options, OutputFormat};
+use crate::error::{ApiError, Failure};
+use crate::openai::Render;
+use crate::{
+    clean, editor, git, ledger, model, openai, retry, structured, ticket, trailers, util, validate,
+};
 
 pub struct Actor {
     messages: Vec<openai::Message>,
     options: Options,
     api_key: String,
+    stage: Vec<String>,
+    ticket: Option<(String, ticket::Placement)>,
+    trailers: Vec<(String, String)>,
+    signing: git::Signing,
+    edit_context: editor::Context,
     pub used_tokens: usize,
 }
 
@@ -21,39 +30,300 @@ impl Actor {
             messages: Vec::new(),
             options,
             api_key,
+            stage: Vec::new(),
+            ticket: None,
+            trailers: Vec::new(),
+            signing: git::Signing::Config,
+            edit_context: editor::Context::default(),
             used_tokens: 0,
         }
     }
 
+    pub fn use_model(&mut self, model: model::Model) {
+        self.options.model = model;
+    }
+
     pub fn add_message(&mut self, message: openai::Message) {
         self.messages.push(message);
     }
 
-    async fn ask(&self) -> anyhow::Result<Vec<String>> {
-        Ok(openai::Request::new(
-            self.options.model.clone().to_string(),
+    /// Files to add to the index right before committing, like `git commit -a` does.
+    pub fn stage_on_commit(&mut self, files: Vec<String>) {
+        self.stage = files;
+    }
+
+    /// Ticket key to put into the generated messages, where `placement` requires it.
+    pub fn reference_ticket(&mut self, ticket: String, placement: ticket::Placement) {
+        self.ticket = Some((ticket, placement));
+    }
+
+    /// Trailers to append to the generated messages.
+    pub fn add_trailers(&mut self, trailers: Vec<(String, String)>) {
+        self.trailers.extend(trailers);
+    }
+
+    /// What to show below the message when editing it.
+    pub fn describe_change(&mut self, context: editor::Context) {
+        self.edit_context = context;
+    }
+
+    pub fn sign(&mut self, signing: git::Signing) {
+        self.signing = signing;
+    }
+
+    fn commit(&self, message: String) -> anyhow::Result<()> {
+        if !self.stage.is_empty() {
+            git::stage(&git::get_repo()?, &self.stage)?;
+        }
+        git::commit(message, &self.signing)
+    }
+
+    fn request(&self, model: model::Model) -> openai::Request {
+        let request = openai::Request::new(
+            model.to_string(),
             self.messages.clone(),
             self.options.n,
             self.options.t,
             self.options.f,
         )
-        .execute(
-            self.api_key.clone(),
-            self.options.print_once,
-            self.options.model.clone(),
-            self.used_tokens,
-        )
-        .await?)
+        .report_usage(&self.options.api_base);
+        match structured::format(model) {
+            Some(format) if self.options.structured => request.structured(format),
+            // like a cheaper model picked for the cost, which is asked for free text
+            _ => request,
+        }
+    }
+
+    /// Asks with the model, or the first fallback that works, which is then kept for
+    /// the next request, like a revision.
+    async fn ask(&mut self) -> anyhow::Result<openai::Completion> {
+        let redirected = !std::io::stdout().is_terminal();
+        let render = if self.options.output == OutputFormat::Json
+            || (self.options.prints_result && redirected)
+        {
+            Render::Silent
+        } else if self.options.print_once || redirected {
+            Render::Once
+        } else {
+            Render::Stream
+        };
+        self.options.budget.check()?;
+        let mut model = self.options.model;
+        let mut fallbacks = self.options.models().into_iter().skip(1);
+        let mut completion = loop {
+            let err = match self
+                .request(model)
+                .execute(
+                    &self.options.api_base,
+                    self.api_key.clone(),
+                    render,
+                    model,
+                    self.used_tokens,
+                    self.options.retry,
+                )
+                .await
+            {
+                Ok(completion) => break completion,
+                Err(err) => err,
+            };
+            let Some(Failure::Api(error)) = err.downcast_ref::<Failure>() else {
+                return Err(err);
+            };
+            if !retry::may_fall_back(error) {
+                return Err(err);
+            }
+            // a model with the same window would run out of context just the same
+            let min_context = if *error == ApiError::ContextLengthExceeded {
+                model.context_size() + 1
+            } else {
+                self.used_tokens + 1
+            };
+            let Some(next) = fallbacks.find(|fallback| fallback.context_size() >= min_context)
+            else {
+                return Err(err);
+            };
+            eprintln!("{} {error} Falling back to {next}.", "!".yellow());
+            model = next;
+        };
+        self.options.model = model;
+        let usage = completion.usage;
+        let entry = ledger::Entry::new(
+            model.to_string(),
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            model.cost(usage.prompt_tokens, usage.completion_tokens),
+            completion.estimated,
+        );
+        if let Err(err) = ledger::record(&entry) {
+            eprintln!("{} Could not record the usage: {err}", "!".yellow());
+        }
+        for choice in &mut completion.choices {
+            *choice = self.finish(choice);
+        }
+        Ok(completion)
+    }
+
+    /// Cleans up a choice, puts the ticket key where it belongs and appends the trailers.
+    /// The header is shortened after placing the key, so that it counts against the limit.
+    fn finish(&self, choice: &str) -> String {
+        let mut message = choice.to_string();
+        if self.options.commit_message {
+            message = clean::commit_message(&message, None);
+        }
+        message = trailers::strip(&message, &trailers::IDENTITY_TOKENS);
+        if let Some((ticket, placement)) = &self.ticket {
+            message = ticket::place(&message, ticket, *placement);
+        }
+        if self.options.commit_message {
+            message = clean::shorten_header(&message, self.options.max_header_length);
+        }
+        trailers::append(&message, &self.trailers)
+    }
+
+    /// Asks once and prints the choices, usage and findings as a JSON document.
+    pub async fn print_json(&mut self) -> anyhow::Result<()> {
+        let completion = self.ask().await?;
+        if completion.cancelled {
+            return Err(Failure::Interrupted.into());
+        }
+        let choices = completion
+            .choices
+            .iter()
+            .enumerate()
+            .map(|(index, message)| {
+                json!({
+                    "index": index,
+                    "message": message,
+                    "findings": validate::findings(message, self.options.max_header_length),
+                })
+            })
+            .collect::<Vec<_>>();
+        let usage = completion.usage;
+        let doc = json!({
+            "model": completion.model.to_string(),
+            "choices": choices,
+            "best": validate::best(&completion.choices, self.options.max_header_length),
+            "usage": usage,
+            "usage_estimated": completion.estimated,
+            "estimated_cost": completion
+                .model
+                .cost(usage.prompt_tokens, usage.completion_tokens),
+        });
+        println!("{}", serde_json::to_string_pretty(&doc)?);
+        Ok(())
+    }
+
+    pub fn dry_run(&self) -> anyhow::Result<()> {
+        if let Some(path) = &self.options.dump_request {
+            std::fs::write(path, self.request(self.options.model).to_json()?)?;
+        }
+        if self.options.output == OutputFormat::Json {
+            let mut prompt_tokens = 0;
+            let mut messages = Vec::new();
+            for message in &self.messages {
+                let tokens = openai::count_token(&message.content)?;
+                prompt_tokens += tokens;
+                messages.push(json!({
+                    "role": message.role,
+                    "content": message.content,
+                    "tokens": tokens,
+                }));
+            }
+            let doc = json!({
+                "model": self.options.model.to_string(),
+                "messages": messages,
+                "prompt_tokens": prompt_tokens,
+                "estimated_cost": self.options.model.cost(prompt_tokens, 0),
+            });
+            println!("{}", serde_json::to_string_pretty(&doc)?);
+            return Ok(());
+        }
+
+        let mut stdout = std::io::stdout();
+        let mut prompt_tokens = 0;
+        for (i, message) in self.messages.iter().enumerate() {
+            let tokens = openai::count_token(&message.content)?;
+            prompt_tokens += tokens;
+            execute!(
+                stdout,
+                Print(format!(
+                    "{}\n",
+                    format!(
+                        "[{}]======= {} tokens",
+                        format!("{i} {:?}", message.role).purple(),
+                        format!("{tokens}").purple()
+                    )
+                    .bright_black()
+                )),
+                Print(format!("{}\n", message.content)),
+            )?;
+        }
+        execute!(
+            stdout,
+            Print(format!("{}\n", "=======================".bright_black())),
+        )?;
+        println!(
+            "{}",
+            format!(
+                "This would use {} prompt tokens with {}, costing you about {} before completion",
+                format!("{prompt_tokens}").purple(),
+                self.options.model.to_string().purple(),
+                format!("~${:0.4}", self.options.model.cost(prompt_tokens, 0)).purple()
+            )
+            .bright_black()
+        );
+
+        if let Some(path) = &self.options.dump_request {
+            println!(
+                "{}",
+                format!("Request body written to {}", path.display()).bright_black()
+            );
+        }
+        Ok(())
+    }
+
+    /// Asks once and lets the user pick one of the choices,
+    /// or picks the best one when running non-interactively.
+    pub async fn generate(&mut self) -> anyhow::Result<Option<String>> {
+        let completion = self.ask().await?;
+        let choices = completion.choices;
+        if completion.cancelled {
+            return self.salvage(choices);
+        }
+        if self.options.non_interactive {
+            return Ok(util::choose_best(choices, self.options.max_header_length));
+        }
+        util::choose_message(choices)
+    }
+
+    /// Offers to edit one of the choices that Ctrl-C cut short, instead of losing them.
+    fn salvage(&self, choices: Vec<String>) -> anyhow::Result<Option<String>> {
+        if self.options.non_interactive || choices.iter().all(|c| c.trim().is_empty()) {
+            return Err(Failure::Interrupted.into());
+        }
+        let keep = Confirm::new("Keep the partial choices for editing?")
+            .with_default(true)
+            .prompt()?;
+        if !keep {
+            return Err(Failure::Interrupted.into());
+        }
+        util::choose_message(choices)?
+            .map(|message| editor::edit(&message, &self.edit_context))
+            .transpose()
     }
 
     pub async fn start(&mut self) -> anyhow::Result<()> {
-        let first_choices = self.ask().await?;
-        let mut message = match util::choose_message(first_choices) {
+        let mut message = match self.generate().await? {
             Some(message) => message,
             None => {
                 return Ok(());
             }
         };
+        if self.options.non_interactive {
+            self.commit(message)?;
+            println!("{} 🎉", "Commit successful!".purple());
+            return Ok(());
+        }
         let tasks = vec![
             Task::Commit.to_str(),
             Task::Edit.to_str(),
@@ -66,18 +336,12 @@ impl Actor {
 
             match Task::from_str(task) {
                 Task::Commit => {
-                    match git::commit(message) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            println!("{e}");
-                            process::exit(1);
-                        }
-                    };
+                    self.commit(message)?;
                     println!("{} 🎉", "Commit successful!".purple());
                     break;
                 }
                 Task::Edit => {
-                    message = edit::edit(message)?;
+                    message = editor::edit(&message, &self.edit_context)?;
                     execute!(
                         std::io::stdout(),
                         Print(format!(
@@ -93,9 +357,7 @@ impl Actor {
                     let input = inquire::Text::new("Revise:").prompt()?;
                     self.add_message(openai::Message::user(input));
 
-                    let choices = self.ask().await?;
-
-                    message = match util::choose_message(choices) {
+                    message = match self.generate().await? {
                         Some(message) => message,
                         None => {
                             return Ok(());
@@ -139,3 +401,38 @@ impl Task {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn test_ticket_counts_against_the_header_length() {
+        let mut options = Options::from(&Config::default());
+        options.commit_message = true;
+        options.max_header_length = Some(40);
+        let header = "feat: add a login form to the home page";
+        assert_eq!(header.len(), 39);
+
+        for (placement, start) in [
+            (
+                ticket::Placement::Prefix,
+                "feat: PROJ-1234 add a login form",
+            ),
+            (
+                ticket::Placement::Scope,
+                "feat(PROJ-1234): add a login form",
+            ),
+        ] {
+            let mut actor = Actor::new(options.clone(), String::new());
+            actor.reference_ticket(String::from("PROJ-1234"), placement);
+            let message = actor.finish(&format!("{header}\n\nBody."));
+            let (finished, body) = message.split_once('\n').unwrap();
+            assert!(finished.chars().count() <= 40, "{finished}");
+            assert!(finished.starts_with(start), "{finished}");
+            assert!(finished.ends_with('…'));
+            assert_eq!(body, "\nBody.");
+        }
+    }
+}
@@ -31,7 +31,7 @@ pub async fn start<W: Write + Send + 'static>(
         let mut current_frame = 0;
         loop {
             current_frame = (current_frame + 1) % frames.len();
-            match execute!(
+            execute!(
                 writer,
                 Clear(ClearType::CurrentLine),
                 MoveToColumn(0),
@@ -39,10 +39,8 @@ pub async fn start<W: Write + Send + 'static>(
                 Print(message.bright_black()),
                 Print(frames[current_frame]),
                 ResetColor
-            ) {
-                Ok(_) => {}
-                Err(_) => {}
-            }
+            )
+            .ok();
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
     })
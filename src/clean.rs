@@ -0,0 +1,290 @@
+use crate::trailers;
+
+/// Column the body of a commit message is wrapped at.
+pub const BODY_WIDTH: usize = 72;
+
+/// Words that start the prose models put around a message, like "Here is your commit message:".
+const CHATTER: [&str; 10] = [
+    "here",
+    "sure",
+    "certainly",
+    "of course",
+    "okay",
+    "ok,",
+    "below",
+    "the following",
+    "this is",
+    "i've",
+];
+
+/// Words that start the prose models put after a message, like "Let me know if ...".
+const CLOSING_CHATTER: [&str; 5] = [
+    "let me know",
+    "i hope",
+    "hope this",
+    "feel free",
+    "this commit message",
+];
+
+/// Cleans up a generated commit message before it is shown or committed: removes prose around
+/// it, a markdown fence or quotes around it, and trailing whitespace, separates the body from
+/// the header, shortens the header to `max_header_length` and wraps the body at [`BODY_WIDTH`].
+pub fn commit_message(message: &str, max_header_length: Option<usize>) -> String {
+    let message = unquote(&unfence(&drop_chatter(message)));
+    let lines = message
+        .lines()
+        .map(str::trim_end)
+        .skip_while(|line| line.is_empty())
+        .collect::<Vec<_>>();
+    let Some((header, body)) = lines.split_first() else {
+        return String::new();
+    };
+
+    let mut header = header.trim().to_string();
+    if let Some(max) = max_header_length {
+        header = shorten(&header, max);
+    }
+    let body = body.join("\n");
+    let body = body.trim_matches('\n');
+    if body.is_empty() {
+        return header;
+    }
+    format!("{header}\n\n{}", wrap(body, BODY_WIDTH))
+}
+
+/// Shortens the header of `message` to `max_header_length`, see [`commit_message`].
+pub fn shorten_header(message: &str, max_header_length: Option<usize>) -> String {
+    let Some(max) = max_header_length else {
+        return message.to_string();
+    };
+    match message.split_once('\n') {
+        Some((header, body)) => format!("{}\n{body}", shorten(header, max)),
+        None => shorten(message, max),
+    }
+}
+
+/// The line without markdown emphasis or headings, in lowercase.
+fn normalize(line: &str) -> String {
+    line.trim_matches(|c: char| c.is_whitespace() || matches!(c, '*' | '#' | '_'))
+        .to_lowercase()
+}
+
+fn is_chatter(line: &str) -> bool {
+    let line = normalize(line);
+    line == "commit message"
+        || (line.ends_with(':') || line.ends_with('!'))
+            && (line.contains("commit message") || CHATTER.iter().any(|w| line.starts_with(w)))
+}
+
+/// Removes lines of prose before the message, a `Commit message:` label in front of it,
+/// and a last paragraph of prose after it.
+fn drop_chatter(message: &str) -> String {
+    let mut lines = message
+        .lines()
+        .skip_while(|line| line.trim().is_empty() || is_chatter(line))
+        .collect::<Vec<_>>();
+    if let Some(first) = lines.first_mut() {
+        let normalized = normalize(first);
+        if normalized.starts_with("commit message:") {
+            let label = first.find(':').map_or(0, |i| i + 1);
+            *first = first[label..].trim_start_matches(['*', '_', ' ']);
+        }
+    }
+
+    let text = lines.join("\n");
+    let text = text.trim_end();
+    if let Some((head, last)) = text.rsplit_once("\n\n") {
+        let last = normalize(last);
+        if CLOSING_CHATTER.iter().any(|w| last.starts_with(w)) {
+            return head.to_string();
+        }
+    }
+    text.to_string()
+}
+
+/// The content of a markdown fence that the message starts with, without what follows it.
+fn unfence(message: &str) -> String {
+    let mut lines = message.trim_start().lines();
+    if !lines
+        .next()
+        .is_some_and(|line| line.trim().starts_with("```"))
+    {
+        return message.to_string();
+    }
+    lines
+        .take_while(|line| !line.trim().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The message without quotes or backticks around all of it.
+fn unquote(message: &str) -> String {
+    let mut message = message.trim();
+    'outer: loop {
+        for (open, close) in [('"', '"'), ('\'', '\''), ('`', '`'), ('“', '”')] {
+            if message.chars().count() >= 2 && message.starts_with(open) && message.ends_with(close)
+            {
+                message = message[open.len_utf8()..message.len() - close.len_utf8()].trim();
+                continue 'outer;
+            }
+        }
+        return message.to_string();
+    }
+}
+
+/// Cuts `header` at the last word that fits into `max` characters, ending it with `…`.
+fn shorten(header: &str, max: usize) -> String {
+    if header.chars().count() <= max || max == 0 {
+        return header.to_string();
+    }
+    let cut = header
+        .char_indices()
+        .nth(max - 1)
+        .map_or(header.len(), |(i, _)| i);
+    let kept = match header[..=cut].rfind(char::is_whitespace) {
+        Some(space) if space > 0 => &header[..space],
+        _ => &header[..cut],
+    };
+    format!("{}…", kept.trim_end_matches([' ', ',', ';', '.']))
+}
+
+/// Wraps the lines of `body` longer than `width`, keeping the indentation of list items.
+/// Code, indented lines, words longer than `width` and the trailer block, which may be all
+/// of the body, stay as they are.
+fn wrap(body: &str, width: usize) -> String {
+    let (text, block) = match trailers::block(body) {
+        Some(block) => (&body[..body.len() - block.len()], block),
+        None if trailers::is_block(body) => ("", body),
+        None => (body, ""),
+    };
+
+    let mut wrapped = Vec::new();
+    let mut in_code = false;
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+        if in_code
+            || line.chars().count() <= width
+            || line.starts_with("    ")
+            || line.starts_with('\t')
+        {
+            wrapped.push(line.to_string());
+            continue;
+        }
+        let content = line.trim_start();
+        let indent = &line[..line.len() - content.len()];
+        let hanging = indent.len() + list_marker_len(content);
+
+        let mut current = indent.to_string();
+        let mut current_len = current.chars().count();
+        let mut empty = true;
+        for word in content.split_whitespace() {
+            let word_len = word.chars().count();
+            if !empty && current_len + 1 + word_len > width {
+                wrapped.push(current);
+                current = " ".repeat(hanging);
+                current_len = hanging;
+                empty = true;
+            }
+            if !empty {
+                current.push(' ');
+                current_len += 1;
+            }
+            current.push_str(word);
+            current_len += word_len;
+            empty = false;
+        }
+        wrapped.push(current);
+    }
+    let mut body = wrapped.join("\n");
+    body.push_str(block);
+    body
+}
+
+/// Length of a `- `, `* `, `+ `, `1. ` or `1) ` at the start of `line`.
+fn list_marker_len(line: &str) -> usize {
+    if line.starts_with(['-', '*', '+']) && line[1..].starts_with(' ') {
+        return 2;
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = &line[digits..];
+    if digits > 0 && (rest.starts_with(". ") || rest.starts_with(") ")) {
+        return digits + 2;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(message: &str) -> String {
+        commit_message(message, Some(72))
+    }
+
+    #[test]
+    fn test_wrapped_messages() {
+        assert_eq!(clean("```\nfeat: add login\n```"), "feat: add login");
+        assert_eq!(
+            clean("Here is your commit message:\n\n```text\nfix(api): handle timeouts\n\nRetry once.\n```\n\nLet me know if you want changes."),
+            "fix(api): handle timeouts\n\nRetry once."
+        );
+        assert_eq!(
+            clean("**Commit message:** feat: add login  "),
+            "feat: add login"
+        );
+        assert_eq!(clean("Sure!\n\"feat: add login\""), "feat: add login");
+        assert_eq!(clean("`fix: typo`\n"), "fix: typo");
+        assert_eq!(
+            clean("feat: add login\n\nAdds a form.\n\nI hope this helps!"),
+            "feat: add login\n\nAdds a form."
+        );
+        assert_eq!(
+            clean("feat: add login\nAdds a form.   \n"),
+            "feat: add login\n\nAdds a form."
+        );
+        assert_eq!(clean("   \n"), "");
+    }
+
+    #[test]
+    fn test_header_length() {
+        let header = format!("feat: {}", "word ".repeat(20));
+        let cleaned = clean(&header);
+        assert!(cleaned.chars().count() <= 72);
+        assert!(cleaned.ends_with("word…"));
+        assert_eq!(commit_message(&header, None), header.trim());
+        assert_eq!(shorten("feat: averyveryverylongword", 10), "feat:…");
+        assert_eq!(shorten("feat:averyveryverylongword", 10), "feat:aver…");
+        assert_eq!(
+            shorten_header("feat: add login\n\nBody.", Some(12)),
+            "feat: add…\n\nBody."
+        );
+    }
+
+    #[test]
+    fn test_body_of_footers_only() {
+        let long = "word ".repeat(20);
+        let message = format!(
+            "feat!: drop v1\n\nBREAKING CHANGE: {}\nRefs: #42",
+            long.trim_end()
+        );
+        assert_eq!(clean(&message), message);
+    }
+
+    #[test]
+    fn test_wrap_body() {
+        let long = "word ".repeat(20);
+        let cleaned = clean(&format!(
+            "feat: add login\n\n{long}\n\n- {long}\n\n    let x = \"{long}\";\n\nRefs: {long}"
+        ));
+        let lines = cleaned.lines().collect::<Vec<_>>();
+        assert_eq!(lines[2].chars().count(), 69);
+        assert_eq!(lines[3], "word word word word word word");
+        assert!(lines[5].starts_with("- word"));
+        assert!(lines[6].starts_with("  word"));
+        assert!(cleaned.contains(&format!("    let x = \"{long}\";")));
+        assert!(cleaned.ends_with(&format!("Refs: {}", long.trim_end())));
+        assert!(lines.iter().filter(|line| line.starts_with("word")).count() == 2);
+    }
+}
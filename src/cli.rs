@@ -1,9 +1,349 @@
 use crate::config::Config;
 use crate::openai::count_token;
-use crate::model;
+use crate::{git, ledger, model, retry};
+use clap::builder::{PossibleValue, PossibleValuesParser, TypedValueParser};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::{cmp, env, process};
+use std::time::Duration;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "turbocommit",
+    version,
+    about = "Generate conventional commit messages with OpenAI GPT models",
+    args_conflicts_with_subcommands = true,
+    disable_version_flag = true
+)]
+pub struct Cli {
+    /// Print version
+    #[arg(short = 'v', long, action = clap::ArgAction::Version)]
+    version: Option<bool>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub commit: CommitArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Generate a commit message for the staged changes (default)
+    Commit(CommitArgs),
+    /// Generate a pull request description for the current branch
+    Pr(PrArgs),
+    /// Generate a changelog from the commits in a range
+    Changelog(ChangelogArgs),
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Manage the git prepare-commit-msg hook
+    Hook {
+        #[command(subcommand)]
+        action: HookAction,
+    },
+    /// Work with the system prompt template
+    Prompt {
+        #[command(subcommand)]
+        action: PromptAction,
+    },
+    /// Summarize the recorded API usage and its cost
+    Usage {
+        /// Group the requests by day, month, repository or model
+        #[arg(long, value_enum, default_value_t)]
+        by: ledger::GroupBy,
+        /// Only count requests from this day on, as YYYY-MM-DD
+        #[arg(long, value_name = "DATE")]
+        since: Option<chrono::NaiveDate>,
+    },
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the man page, or write one page per command into a directory
+    Man {
+        #[arg(long, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Print the path of the config file
+    Path,
+    /// Print the effective configuration
+    Show {
+        /// Show where each value came from
+        #[arg(long)]
+        origin: bool,
+        /// Apply a profile first
+        #[arg(long, value_name = "NAME")]
+        profile: Option<String>,
+    },
+    /// Write a config file listing the defaults, commented out, if there is none yet
+    Init {
+        /// Create `.turbocommit.yaml` in the current repository instead
+        #[arg(long)]
+        repo: bool,
+    },
+    /// Upgrade a config file written by an older version of turbocommit
+    Migrate {
+        /// Migrate `.turbocommit.yaml` in the current repository instead
+        #[arg(long)]
+        repo: bool,
+    },
+    /// Set a single value, keeping the rest of the file as it is
+    Set {
+        key: String,
+        /// Parsed as YAML, e.g. `0.5`, `true` or `[api, cli]`
+        value: String,
+        /// Edit `.turbocommit.yaml` in the current repository instead
+        #[arg(long)]
+        repo: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PromptAction {
+    /// Print the system prompt as it would be sent for the staged changes
+    Render {
+        /// Use the system message of a profile from the config
+        #[arg(long, value_name = "NAME")]
+        profile: Option<String>,
+        /// Value for `{{user_instruction}}`, given after `--`
+        #[arg(last = true, value_name = "INSTRUCTION")]
+        instruction: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum HookAction {
+    /// Install the prepare-commit-msg hook into the current repository
+    Install,
+    /// Remove the prepare-commit-msg hook installed by turbocommit
+    Uninstall,
+    /// Run the hook (called by git)
+    #[command(hide = true)]
+    Run {
+        /// Path to the commit message file
+        file: PathBuf,
+        /// Source of the commit message
+        source: Option<String>,
+        /// Commit object name
+        sha: Option<String>,
+    },
+}
+
+#[derive(Debug, Args, Default)]
+pub struct GenerationArgs {
+    /// Use the settings of a profile from the config
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Number of choices to generate
+    #[arg(short, value_name = "N", value_parser = clap::value_parser!(i32).range(1..))]
+    pub n: Option<i32>,
+
+    /// Model to use
+    #[arg(short, long, value_name = "MODEL", value_parser = model_parser())]
+    pub model: Option<model::Model>,
+
+    /// Temperature (0.0 <= t <= 2.0)
+    #[arg(short, value_name = "T", value_parser = temperature)]
+    pub t: Option<f64>,
+
+    /// Frequency penalty (-2.0 <= f <= 2.0)
+    #[arg(short, value_name = "F", value_parser = frequency_penalty)]
+    pub f: Option<f64>,
+
+    /// Will not print tokens as they are generated
+    #[arg(short, long)]
+    pub print_once: bool,
+
+    /// Never prompt: pick the best choice automatically and leave out files if the diff is too large
+    #[arg(short = 'y', long = "yes", visible_alias = "non-interactive")]
+    pub non_interactive: bool,
+
+    /// Output format. `json` never prompts or commits and prints a single JSON document
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    /// Dry run. Shows what would be sent without asking the AI
+    #[arg(short, long)]
+    pub dry_run: bool,
+
+    /// Dry run, and write the exact JSON request body to FILE
+    #[arg(long, value_name = "FILE")]
+    pub dump_request: Option<PathBuf>,
+
+    /// Extra explanation or instruction for the AI, given after `--`
+    #[arg(last = true, value_name = "INSTRUCTION")]
+    pub instruction: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Args, Default)]
+pub struct CommitArgs {
+    /// Generate a message for a patch file (`-` for stdin) and print it instead of committing
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["all", "include_untracked"])]
+    pub diff_file: Option<PathBuf>,
+
+    /// Include all modified tracked files, staged or not, and stage them on commit
+    #[arg(short, long)]
+    pub all: bool,
+
+    /// Like --all, but also include untracked files
+    #[arg(short = 'u', long)]
+    pub include_untracked: bool,
+
+    /// Add a Signed-off-by trailer with your git identity
+    #[arg(short, long)]
+    pub signoff: bool,
+
+    /// Add a Co-authored-by trailer for the recent author matching NAME, or for `Name <email>`
+    #[arg(long, value_name = "NAME")]
+    pub co_author: Vec<String>,
+
+    /// Pick co-authors from the recent authors
+    #[arg(long)]
+    pub pick_co_authors: bool,
+
+    /// Add a trailer like `Reviewed-by: Name <email>`
+    #[arg(long, value_name = "TRAILER")]
+    pub trailer: Vec<String>,
+
+    /// Sign the commit, optionally with KEYID instead of `user.signingkey`
+    #[arg(
+        short = 'S',
+        long,
+        value_name = "KEYID",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    pub gpg_sign: Option<String>,
+
+    /// Do not sign the commit, even if `commit.gpgsign` is set
+    #[arg(long, conflicts_with = "gpg_sign")]
+    pub no_gpg_sign: bool,
+
+    /// Ask for the fields of a conventional commit as JSON and render the message from them
+    #[arg(long)]
+    pub structured: bool,
+
+    #[command(flatten)]
+    pub generation: GenerationArgs,
+}
+
+impl CommitArgs {
+    pub fn signing(&self) -> git::Signing {
+        match &self.gpg_sign {
+            _ if self.no_gpg_sign => git::Signing::Off,
+            Some(key) if key.is_empty() => git::Signing::Sign(None),
+            Some(key) => git::Signing::Sign(Some(key.clone())),
+            None => git::Signing::Config,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PrArgs {
+    /// Branch to compare against (defaults to main or master)
+    #[arg(short, long)]
+    pub base: Option<String>,
+
+    #[command(flatten)]
+    pub generation: GenerationArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ChangelogArgs {
+    /// Revision range to summarize (defaults to <latest tag>..HEAD)
+    pub range: Option<String>,
+
+    #[command(flatten)]
+    pub generation: GenerationArgs,
+}
+
+fn model_parser() -> impl TypedValueParser<Value = model::Model> {
+    PossibleValuesParser::new(
+        model::Model::all()
+            .iter()
+            .map(|model| PossibleValue::new(model.to_string())),
+    )
+    .map(|model| model::Model::from_str(&model).unwrap_or_default())
+}
+
+fn temperature(s: &str) -> Result<f64, String> {
+    let t: f64 = s.parse().map_err(|_| String::from("expected a float"))?;
+    if (0.0..=2.0).contains(&t) {
+        Ok(t)
+    } else {
+        Err(String::from("must be between 0.0 and 2.0"))
+    }
+}
+
+fn frequency_penalty(s: &str) -> Result<f64, String> {
+    let f: f64 = s.parse().map_err(|_| String::from("expected a float"))?;
+    if (-2.0..=2.0).contains(&f) {
+        Ok(f)
+    } else {
+        Err(String::from("must be between -2.0 and 2.0"))
+    }
+}
+
+impl Cli {
+    pub fn command_with_help(config: &Config) -> clap::Command {
+        Self::command()
+            .before_help(banner())
+            .after_help(footer(config))
+    }
+
+    pub fn parse_with_help(config: &Config) -> Self {
+        let matches = Self::command_with_help(config).get_matches();
+        Self::from_arg_matches(&matches).unwrap_or_else(|err| err.exit())
+    }
+
+    /// The generation options of the command that will run, if it generates anything.
+    pub fn generation(&self) -> Option<&GenerationArgs> {
+        match &self.command {
+            None => Some(&self.commit.generation),
+            Some(Command::Commit(args)) => Some(&args.generation),
+            Some(Command::Pr(args)) => Some(&args.generation),
+            Some(Command::Changelog(args)) => Some(&args.generation),
+            Some(_) => None,
+        }
+    }
+}
+
+pub fn print_completions(shell: clap_complete::Shell, config: &Config) {
+    let mut cmd = Cli::command_with_help(config);
+    let name = cmd.get_name().to_string();
+    clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+}
+
+pub fn print_man(out_dir: Option<&std::path::Path>, config: &Config) -> std::io::Result<()> {
+    colored::control::set_override(false);
+    let cmd = Cli::command_with_help(config);
+    match out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            clap_mangen::generate_to(cmd, dir)
+        }
+        None => clap_mangen::Man::new(cmd).render(&mut std::io::stdout()),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Options {
@@ -13,6 +353,24 @@ pub struct Options {
     pub f: f64,
     pub print_once: bool,
     pub model: model::Model,
+    pub fallback_models: Vec<model::Model>,
+    pub dry_run: bool,
+    pub dump_request: Option<PathBuf>,
+    pub non_interactive: bool,
+    pub output: OutputFormat,
+    pub api_base: String,
+    pub api_key_env: String,
+    pub retry: retry::Policy,
+    pub budget: ledger::Budget,
+    pub confirm_cost_above: Option<f64>,
+    /// Whether the commit message is requested as JSON fields, see [`crate::structured`].
+    pub structured: bool,
+    /// Whether the choices are commit messages, which are cleaned up, see [`crate::clean`].
+    pub commit_message: bool,
+    pub max_header_length: Option<usize>,
+    /// Whether the chosen choice is printed as the result, so that stdout carries nothing
+    /// else when it is not a terminal.
+    pub prints_result: bool,
 }
 
 impl From<&Config> for Options {
@@ -24,159 +382,111 @@ impl From<&Config> for Options {
             f: config.default_frequency_penalty,
             print_once: config.disable_print_as_stream,
             model: config.model,
+            fallback_models: config.fallback_models.clone(),
+            dry_run: false,
+            dump_request: None,
+            non_interactive: false,
+            output: OutputFormat::Text,
+            api_base: config.api_base.clone(),
+            api_key_env: config.api_key_env.clone(),
+            retry: retry::Policy {
+                max_attempts: config.max_attempts.max(1),
+                timeout: Duration::from_secs(config.request_timeout),
+            },
+            budget: ledger::Budget {
+                daily: config.daily_budget,
+                monthly: config.monthly_budget,
+                action: config.budget_action,
+            },
+            confirm_cost_above: config.confirm_cost_above,
+            structured: false,
+            commit_message: false,
+            max_header_length: config.max_header_length,
+            prints_result: false,
         }
     }
 }
 
 impl Options {
-    pub fn new<I>(args: I, conf: &Config) -> Self
-    where
-        I: Iterator<Item = String>,
-    {
-        let mut opts = Self::from(conf);
-        let mut iter = args.skip(1);
-        let mut msg = String::new();
-
-        while let Some(arg) = iter.next() {
-            match arg.as_str() {
-                "-n" => {
-                    if let Some(n) = iter.next() {
-                        opts.n = n.parse().map_or_else(
-                            |_| {
-                                println!(
-                                    "{} {}",
-                                    "Could not parse n.".red(),
-                                    "Please enter an integer.".bright_black()
-                                );
-                                process::exit(1);
-                            },
-                            |n| cmp::max(1, n),
-                        );
-                    }
-                }
-                "-t" => {
-                    if let Some(t) = iter.next() {
-                        opts.t = t.parse().map_or_else(
-                            |_| {
-                                println!(
-                                    "{} {}",
-                                    "Could not parse t.".red(),
-                                    "Please enter a float between 0 and 2.".bright_black()
-                                );
-                                process::exit(1);
-                            },
-                            |t: f64| t.clamp(0.0, 2.0),
-                        );
-                    }
-                }
-                "-f" => {
-                    if let Some(f) = iter.next() {
-                        opts.f = f.parse().map_or_else(
-                            |_| {
-                                println!(
-                                    "{} {}",
-                                    "Could not parse f.".red(),
-                                    "Please enter a float between -2.0 and 2.0.".bright_black()
-                                );
-                                process::exit(1);
-                            },
-                            |f: f64| f.clamp(-2.0, 2.0),
-                        );
-                    }
-                }
-                "-p" | "--print-once" => {
-                    opts.print_once = true;
-                }
-                "-m" | "--model" => {
-                    if let Some(model) = iter.next() {
-                        opts.model = match model::Model::from_str(&model) {
-                            Ok(model) => model,
-                            Err(err) => {
-                                println!(
-                                    "{} {}",
-                                    format!("Could not parse model: {}", err).red(),
-                                    "Please enter a valid model.".bright_black()
-                                );
-                                process::exit(1);
-                            }
-                        };
-                    }
-                }
-                "-h" | "--help" => help(),
-                "-v" | "--version" => {
-                    println!("turbocommit version {}", env!("CARGO_PKG_VERSION").purple());
-                    process::exit(0);
-                }
-                _ => {
-                    if arg.starts_with('-') {
-                        println!(
-                            "{} {} {}",
-                            "Unknown option: ".red(),
-                            arg.purple().bold(),
-                            "\nPlease use -h or --help for help.".bright_black()
-                        );
-                        process::exit(1);
-                    }
-                    msg.push_str(&arg);
-                    msg.push(' ');
-                }
+    /// The model, followed by its fallbacks, without those that cannot be asked for
+    /// structured output when it is on.
+    pub fn models(&self) -> Vec<model::Model> {
+        let mut models = vec![self.model];
+        for model in &self.fallback_models {
+            if !models.contains(model)
+                && (!self.structured || crate::structured::format(*model).is_some())
+            {
+                models.push(*model);
             }
         }
-        if !msg.is_empty() {
+        models
+    }
+
+    pub fn new(args: &GenerationArgs, conf: &Config) -> Self {
+        let mut opts = Self::from(conf);
+        if let Some(n) = args.n {
+            opts.n = n;
+        }
+        if let Some(t) = args.t {
+            opts.t = t;
+        }
+        if let Some(f) = args.f {
+            opts.f = f;
+        }
+        if let Some(model) = args.model {
+            opts.model = model;
+        }
+        opts.output = args.output;
+        opts.non_interactive = args.non_interactive || args.output == OutputFormat::Json;
+        opts.print_once |= args.print_once || args.non_interactive;
+        opts.dry_run = args.dry_run || args.dump_request.is_some();
+        opts.dump_request.clone_from(&args.dump_request);
+
+        let msg = args.instruction.join(" ");
+        if !msg.trim().is_empty() {
             opts.msg = format!("User Explanation/Instruction: '{}'", msg.trim());
         }
         opts
     }
 }
 
-fn help() {
-    println!("{}", "    __             __".red());
-    println!("{}", "   / /___  _______/ /_  ____".red());
-    println!("{}", "  / __/ / / / ___/ __ \\/ __ \\".yellow());
-    println!("{}", " / /_/ /_/ / /  / /_/ / /_/ /".green());
-    println!(
-        "{}{}",
-        " \\__/\\__,_/_/  /_.___/\\____/       ".blue(),
-        "_ __".purple()
-    );
-    println!("{}", "   _________  ____ ___  ____ ___  (_) /_".purple());
-    println!("{}", "  / ___/ __ \\/ __ `__ \\/ __ `__ \\/ / __/".red());
-    println!("{}", " / /__/ /_/ / / / / / / / / / / / / /_".yellow());
-    println!("{}", " \\___/\\____/_/ /_/ /_/_/ /_/ /_/_/\\__/".green());
-
-    println!("\nUsage: turbocommit [options] [message]\n");
-    println!("Options:");
-    println!("  -n <n>   Number of choices to generate\n",);
-    println!("  -m <m>   Model to use\n  --model <m>",);
-    println!("    Available models:");
-    model::Model::all().iter().for_each(|model| {
-        println!("     {}", model.to_string().bright_black());
-    });
-    println!();
-    println!("  -p       Will not print tokens as they are generated.\n  --print-once \n",);
-    println!(
-        "  -t <t>   Temperature (|t| 0.0 < t < 2.0)\n{}\n",
-        "(https://platform.openai.com/docs/api-reference/chat/create#chat/create-temperature)"
-            .bright_black()
-    );
-    println!(
-        "  -f <f>   Frequency penalty (|f| -2.0 < f < 2.0)\n{}\n",
-        "(https://platform.openai.com/docs/api-reference/chat/create#chat/create-frequency-penalty)"
-            .bright_black()
-    );
-    println!("Anything else will be concatenated into an extra message given to the AI\n");
-    println!("You can change the defaults for these options and the system message prompt in the config file, that is created the first time running the program\n{}",
-        home::home_dir().unwrap_or_else(|| "".into()).join(".turbocommit.yaml").display());
-    println!("To go back to the default system message, delete the config file.\n");
-    println!(
-        "\nThe system message is about ~{} tokens long",
+fn banner() -> String {
+    [
+        "    __             __".red().to_string(),
+        "   / /___  _______/ /_  ____".red().to_string(),
+        "  / __/ / / / ___/ __ \\/ __ \\".yellow().to_string(),
+        " / /_/ /_/ / /  / /_/ / /_/ /".green().to_string(),
         format!(
-            "{}",
-            count_token(&crate::config::Config::load().system_msg).unwrap_or(0)
-        )
-        .green()
-    );
-    process::exit(1);
+            "{}{}",
+            " \\__/\\__,_/_/  /_.___/\\____/       ".blue(),
+            "_ __".purple()
+        ),
+        "   _________  ____ ___  ____ ___  (_) /_"
+            .purple()
+            .to_string(),
+        "  / ___/ __ \\/ __ `__ \\/ __ `__ \\/ / __/"
+            .red()
+            .to_string(),
+        " / /__/ /_/ / / / / / / / / / / / / /_"
+            .yellow()
+            .to_string(),
+        " \\___/\\____/_/ /_/ /_/_/ /_/ /_/_/\\__/"
+            .green()
+            .to_string(),
+    ]
+    .join("\n")
+}
+
+fn footer(config: &Config) -> String {
+    format!(
+        "{}\n\n\
+        You can change the defaults for these options and the system message prompt in the config file, which `turbocommit config init` creates\n{}\n\
+        To go back to the default system message, delete the config file.\n\n\
+        The system message is about ~{} tokens long",
+        "(https://platform.openai.com/docs/api-reference/chat/create)".bright_black(),
+        Config::path().map_or_else(|err| err.to_string(), |path| path.display().to_string()),
+        count_token(&config.system_prompt()).unwrap_or(0).to_string().green()
+    )
 }
 
 #[cfg(test)]
@@ -184,6 +494,10 @@ mod tests {
     use super::*;
     use crate::config::Config;
 
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(args).unwrap()
+    }
+
     #[test]
     fn test_options_from_config() {
         let config = Config::default();
@@ -199,7 +513,7 @@ mod tests {
     #[test]
     fn test_options_new() {
         let config = Config::default();
-        let args = vec![
+        let cli = parse(&[
             "turbocommit",
             "-n",
             "3",
@@ -210,18 +524,120 @@ mod tests {
             "--print-once",
             "--model",
             "gpt-4",
+            "--",
             "test",
             "commit",
-        ];
-        let args = args.into_iter().map(String::from).collect::<Vec<String>>();
-        let options = Options::new(args.into_iter(), &config);
+        ]);
+        assert!(cli.command.is_none());
+        let options = Options::new(&cli.commit.generation, &config);
         println!("{:#?}", options);
 
         assert_eq!(options.n, 3);
         assert_eq!(options.t, 1.0);
         assert_eq!(options.f, 0.5);
-        assert_eq!(options.print_once, true);
+        assert!(options.print_once);
         assert_eq!(options.model, model::Model::Gpt4);
         assert_eq!(options.msg, "User Explanation/Instruction: 'test commit'");
     }
+
+    #[test]
+    fn test_options_dry_run() {
+        let config = Config::default();
+        let cli = parse(&["turbocommit", "commit", "--dump-request", "req.json"]);
+        let Some(Command::Commit(args)) = cli.command else {
+            panic!("expected the commit subcommand");
+        };
+        let options = Options::new(&args.generation, &config);
+
+        assert!(options.dry_run);
+        assert_eq!(options.dump_request, Some(PathBuf::from("req.json")));
+        assert!(options.msg.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        assert!(Cli::try_parse_from(["turbocommit", "-n"]).is_err());
+        assert!(Cli::try_parse_from(["turbocommit", "-n", "0"]).is_err());
+        assert!(Cli::try_parse_from(["turbocommit", "-t", "3"]).is_err());
+        assert!(Cli::try_parse_from(["turbocommit", "-m", "gpt-2"]).is_err());
+        assert!(Cli::try_parse_from(["turbocommit", "free", "text"]).is_err());
+    }
+
+    #[test]
+    fn test_subcommands() {
+        let cli = parse(&["turbocommit", "pr", "--base", "develop", "--", "be brief"]);
+        let Some(Command::Pr(args)) = cli.command else {
+            panic!("expected the pr subcommand");
+        };
+        assert_eq!(args.base.as_deref(), Some("develop"));
+        assert_eq!(args.generation.instruction, vec!["be brief"]);
+
+        let cli = parse(&["turbocommit", "-S"]);
+        assert_eq!(cli.commit.signing(), git::Signing::Sign(None));
+        let cli = parse(&["turbocommit", "--gpg-sign=ABCD", "-n", "2"]);
+        assert_eq!(
+            cli.commit.signing(),
+            git::Signing::Sign(Some(String::from("ABCD")))
+        );
+        let cli = parse(&["turbocommit", "--no-gpg-sign"]);
+        assert_eq!(cli.commit.signing(), git::Signing::Off);
+        assert_eq!(
+            parse(&["turbocommit"]).commit.signing(),
+            git::Signing::Config
+        );
+
+        let cli = parse(&["turbocommit", "prompt", "render", "--", "be brief"]);
+        let Some(Command::Prompt {
+            action: PromptAction::Render { instruction, .. },
+        }) = cli.command
+        else {
+            panic!("expected the prompt subcommand");
+        };
+        assert_eq!(instruction, vec!["be brief"]);
+
+        let cli = parse(&["turbocommit", "changelog", "--profile", "quick"]);
+        assert_eq!(
+            cli.generation().and_then(|args| args.profile.as_deref()),
+            Some("quick")
+        );
+
+        let cli = parse(&["turbocommit", "changelog", "v1.0.0..HEAD"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Changelog(ChangelogArgs { range: Some(_), .. }))
+        ));
+
+        let cli = parse(&[
+            "turbocommit",
+            "usage",
+            "--by",
+            "repo",
+            "--since",
+            "2026-10-01",
+        ]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Usage {
+                by: ledger::GroupBy::Repo,
+                since: Some(_)
+            })
+        ));
+    }
+
+    #[test]
+    fn test_completions_include_models() {
+        let mut cmd = Cli::command_with_help(&Config::default());
+        let mut out = Vec::new();
+        clap_complete::generate(
+            clap_complete::Shell::Bash,
+            &mut cmd,
+            "turbocommit",
+            &mut out,
+        );
+        let script = String::from_utf8(out).unwrap();
+
+        for model in model::Model::all() {
+            assert!(script.contains(&model.to_string()), "{model} missing");
+        }
+    }
 }
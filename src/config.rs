@@ -1,12 +1,32 @@
-use crate::{model};
+use crate::{git, ledger, migrate, model, ticket};
 use colored::Colorize;
 use serde::{Deserialize, Serialize};
-use std::process;
+use serde_yaml::{Mapping, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{env, fmt};
+
+pub const PR_SYSTEM_MSG: &str = "You write pull request descriptions. You will receive the git diff of a branch against its base branch.
+Respond with a short title on the first line, followed by a blank line and a concise description in markdown that explains what changed and why.
+Group related changes, mention anything reviewers should pay special attention to, and do not list every file.
+The user may give you more specific instructions or extra information.";
+
+pub const CHANGELOG_SYSTEM_MSG: &str = "You write changelogs. You will receive a list of commit messages, newest first.
+Respond with a markdown changelog grouped into sections like Features, Fixes and Other Changes, with one short bullet per user-facing change.
+Merge duplicates, leave out purely internal changes like chores and refactors unless they matter to users, and do not invent changes.
+The user may give you more specific instructions or extra information.";
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
+    /// The config format version, see [`migrate`].
+    #[serde(default)]
+    pub version: u32,
     #[serde(default)]
     pub model: model::Model,
+    /// Models to use instead, in this order, when the diff does not fit into `model`
+    /// or a request with `model` fails.
+    #[serde(default)]
+    pub fallback_models: Vec<model::Model>,
     #[serde(default)]
     pub default_temperature: f64,
     #[serde(default)]
@@ -17,12 +37,125 @@ pub struct Config {
     pub disable_print_as_stream: bool,
     #[serde(default)]
     pub system_msg: String,
+    /// Appended to the system message, e.g. project specific conventions.
+    #[serde(default)]
+    pub prompt_additions: String,
+    /// Asks for the fields of a conventional commit as JSON, and renders the message from them.
+    #[serde(default)]
+    pub structured_output: bool,
+    /// Commit message headers are cut at the last word that fits, `null` to keep them whole.
+    #[serde(default)]
+    pub max_header_length: Option<usize>,
+    /// Scopes the AI should choose from.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Pathspecs of files that are left out of the diff, e.g. lock files.
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Regex finding the ticket key in the branch name, its first group if it has one.
+    #[serde(default)]
+    pub ticket_pattern: String,
+    /// Where the ticket key has to appear in the message.
+    #[serde(default)]
+    pub ticket_placement: ticket::Placement,
+    /// Always add a Signed-off-by trailer.
+    #[serde(default)]
+    pub signoff: bool,
+    /// Trailers added to every message, like `Reviewed-by: Name <email>`.
+    #[serde(default)]
+    pub trailers: Vec<String>,
+    /// Base URL of the OpenAI compatible API to use.
+    #[serde(default)]
+    pub api_base: String,
+    /// Environment variable that holds the API key.
+    #[serde(default)]
+    pub api_key_env: String,
+    /// How often a request is tried when it fails with a rate limit, server or network error.
+    #[serde(default)]
+    pub max_attempts: u32,
+    /// Seconds to wait for a complete response, over all attempts.
+    #[serde(default)]
+    pub request_timeout: u64,
+    /// Asks before sending a request that may cost more USD than this.
+    #[serde(default)]
+    pub confirm_cost_above: Option<f64>,
+    /// USD that may be spent per UTC day, over all repositories.
+    #[serde(default)]
+    pub daily_budget: Option<f64>,
+    /// USD that may be spent per UTC month, over all repositories.
+    #[serde(default)]
+    pub monthly_budget: Option<f64>,
+    /// Whether a used up budget only warns, or blocks further requests.
+    #[serde(default)]
+    pub budget_action: ledger::BudgetAction,
+    /// Named sets of settings, selected with `--profile`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Profile used when `--profile` is not given.
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    /// Where each field's value came from.
+    #[serde(skip)]
+    pub origins: BTreeMap<String, Origin>,
+    /// Config files that `turbocommit config migrate` would change.
+    #[serde(skip)]
+    pub outdated: Vec<PathBuf>,
+}
+
+/// Settings that override the config when the profile is selected.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<model::Model>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number_of_choices: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system_msg: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_base: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+}
+
+/// Keys that a repo config may not set, directly or in a profile. A cloned repository
+/// could otherwise send the diff, with any environment variable as the API key, to its
+/// own host.
+const USER_ONLY_KEYS: [&str; 2] = ["api_base", "api_key_env"];
+
+/// The layer a config value came from, in increasing order of precedence.
+/// Command line options are applied on top of all of them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    User(PathBuf),
+    Repo(PathBuf),
+    Profile(String),
+    Env(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::User(path) => write!(f, "user config ({})", path.display()),
+            Self::Repo(path) => write!(f, "repo config ({})", path.display()),
+            Self::Env(var) => write!(f, "environment (${var})"),
+            Self::Profile(name) => write!(f, "profile {name}"),
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            version: migrate::CURRENT_VERSION,
             model: model::Model::Gpt35Turbo,
+            fallback_models: Vec::new(),
             default_temperature: 0.8,
             default_frequency_penalty: 0.0,
             default_number_of_choices: 2,
@@ -42,89 +175,745 @@ Ensure that all commits follow these guidelines
 - You may provide a longer commit body after the short description. Body should start one blank line after the description and can consist of any number of newline-separated paragraphs
 
 No yapping!"),
+            prompt_additions: String::new(),
+            structured_output: false,
+            max_header_length: Some(72),
+            scopes: Vec::new(),
+            ignore: Vec::new(),
+            ticket_pattern: String::from("[A-Z][A-Z0-9]+-[0-9]+"),
+            ticket_placement: ticket::Placement::Off,
+            signoff: false,
+            trailers: Vec::new(),
+            api_base: String::from("https://api.openai.com/v1"),
+            api_key_env: String::from("OPENAI_API_KEY"),
+            max_attempts: 3,
+            request_timeout: 120,
+            confirm_cost_above: Some(0.5),
+            daily_budget: None,
+            monthly_budget: None,
+            budget_action: ledger::BudgetAction::Warn,
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            origins: BTreeMap::new(),
+            outdated: Vec::new(),
         }
     }
 }
 
 impl Config {
+    /// Loads the user config, then the repo config, then `TURBOCOMMIT_*`
+    /// environment variables, each overriding single fields of the previous.
     pub fn load() -> Self {
-        let path = home::home_dir().map_or_else(
-            || {
-                println!("{}", "Unable to find home directory.".red());
-                process::exit(1);
-            },
-            |path| path.join(".turbocommit.yaml"),
-        );
-        match std::fs::read_to_string(path) {
-            Ok(config) => match serde_yaml::from_str::<Self>(&config) {
-                Ok(config) => { 
-                    if config.system_msg.trim().is_empty() {
-                        let mut config = config;
-                        config.system_msg = Self::default().system_msg;
-                        return config;
+        // without a home directory there is no user config, only the repo config
+        let user_path = Self::path().ok();
+        let mut files = user_path
+            .iter()
+            .map(|path| (Origin::User(path.clone()), path.clone()))
+            .collect::<Vec<_>>();
+        if let Some(path) = Self::repo_path().filter(|path| Some(path) != user_path.as_ref()) {
+            files.push((Origin::Repo(path.clone()), path));
+        }
+
+        let mut outdated = Vec::new();
+        let mut layers = Vec::new();
+        for (origin, path) in files {
+            let mut layer = Self::read_layer(&path);
+            if let Some(layer) = &mut layer {
+                if !migrate::pending(layer).is_empty() {
+                    outdated.push(path);
+                }
+                migrate::rename_keys(layer);
+            }
+            layers.push((origin, layer));
+        }
+        layers.extend(Self::env_layers());
+
+        let mut config = Self::from_layers(layers);
+        config.outdated = outdated;
+        config
+    }
+
+    /// Merges the fields of `layers` over the defaults, later layers taking precedence.
+    /// A field with an invalid value is ignored with a warning, keeping the others, and so
+    /// are [`USER_ONLY_KEYS`] and profiles that set them in a repo config.
+    fn from_layers(layers: Vec<(Origin, Option<Mapping>)>) -> Self {
+        let Ok(Value::Mapping(defaults)) = serde_yaml::to_value(Self::default()) else {
+            return Self::default();
+        };
+        let mut merged = defaults.clone();
+        let mut origins: BTreeMap<String, Origin> = merged
+            .keys()
+            .filter_map(Value::as_str)
+            .map(|key| (key.to_string(), Origin::Default))
+            .collect();
+
+        for (origin, layer) in layers {
+            let Some(layer) = layer else {
+                continue;
+            };
+            for (key, value) in layer {
+                let Some(name) = key.as_str() else {
+                    continue;
+                };
+                if !origins.contains_key(name) {
+                    continue;
+                }
+                let value = match origin {
+                    Origin::Repo(_) if USER_ONLY_KEYS.contains(&name) => {
+                        warn_user_only(name, &origin);
+                        continue;
                     }
-                    config
-                },
+                    Origin::Repo(_) if name == "profiles" => without_user_only_keys(value, &origin),
+                    _ => value,
+                };
+                let mut single = defaults.clone();
+                single.insert(key.clone(), value.clone());
+                if let Err(err) = serde_yaml::from_value::<Self>(Value::Mapping(single)) {
+                    eprintln!(
+                        "{}\n{}",
+                        format!("Invalid value for `{name}` in {origin}: {err}").red(),
+                        "Ignoring it.".bright_black()
+                    );
+                    continue;
+                }
+                origins.insert(name.to_string(), origin.clone());
+                // profiles of different layers are merged by name
+                if let (Some(Value::Mapping(profiles)), Value::Mapping(more)) =
+                    (merged.get_mut(&key), &value)
+                {
+                    profiles.extend(more.clone());
+                    continue;
+                }
+                merged.insert(key, value);
+            }
+        }
+
+        let mut config = match serde_yaml::from_value::<Self>(Value::Mapping(merged)) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!(
+                    "{}\n{}",
+                    format!("Unable to parse config: {}", err).red(),
+                    "Using default config.".bright_black()
+                );
+                return Self::default();
+            }
+        };
+        if config.system_msg.trim().is_empty() {
+            config.system_msg = Self::default().system_msg;
+            origins.insert(String::from("system_msg"), Origin::Default);
+        }
+        config.origins = origins;
+        config
+    }
+
+    fn read_layer(path: &Path) -> Option<Mapping> {
+        match std::fs::read_to_string(path) {
+            Ok(config) => match serde_yaml::from_str::<Option<Mapping>>(&config) {
+                Ok(config) => Some(config.unwrap_or_default()),
                 Err(err) => {
-                    println!(
+                    eprintln!(
                         "{}\n{}",
-                        format!("Unable to parse config file: {}", err).red(),
-                        "Using default config.".bright_black()
+                        format!("Unable to parse config file {}: {}", path.display(), err).red(),
+                        "Ignoring it.".bright_black()
                     );
-                    Default::default()
+                    None
                 }
             },
             Err(err) => {
-                match err.kind() {
-                    std::io::ErrorKind::NotFound => {
-                        println!("{}", "Using default config.".bright_black());
-                    }
-                    _ => {
-                        println!(
-                            "{}\n{}",
-                            format!("Unable to read config file: {}\n", err).red(),
-                            "Using default config.".bright_black()
-                        );
-                    }
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    eprintln!(
+                        "{}\n{}",
+                        format!("Unable to read config file {}: {}\n", path.display(), err).red(),
+                        "Ignoring it.".bright_black()
+                    );
                 }
-                Default::default()
+                None
             }
         }
     }
-    pub fn save_if_changed(&self) -> Result<(), std::io::Error> {
-        let path = home::home_dir().map_or_else(
-            || {
-                println!("{}", "Unable to find home directory.".red());
-                process::exit(1);
-            },
-            |path| path.join(".turbocommit.yaml"),
-        );
-        let config = match serde_yaml::to_string(self) {
-            Ok(config) => config,
-            Err(err) => {
-                println!("{}", format!("Unable to serialize config: {}", err).red());
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Unable to serialize config",
-                ));
+
+    /// One layer per field set through a `TURBOCOMMIT_<FIELD>` variable, parsed as YAML.
+    fn env_layers() -> Vec<(Origin, Option<Mapping>)> {
+        let Ok(Value::Mapping(defaults)) = serde_yaml::to_value(Self::default()) else {
+            return Vec::new();
+        };
+        defaults
+            .keys()
+            .filter_map(Value::as_str)
+            .filter(|key| *key != "version")
+            .filter_map(|key| {
+                let var = format!("TURBOCOMMIT_{}", key.to_uppercase());
+                let value = env::var(&var).ok()?;
+                let value = serde_yaml::from_str(&value).unwrap_or(Value::String(value));
+                let mut layer = Mapping::new();
+                layer.insert(Value::from(key), value);
+                Some((Origin::Env(var), Some(layer)))
+            })
+            .collect()
+    }
+
+    /// The config with the settings of profile `name`, or of `default_profile`, applied.
+    pub fn with_profile(mut self, name: Option<&str>) -> anyhow::Result<Self> {
+        let Some(name) = name.or(self.default_profile.as_deref()).map(String::from) else {
+            return Ok(self);
+        };
+        let Some(profile) = self.profiles.get(&name).cloned() else {
+            let known = self.profiles.keys().cloned().collect::<Vec<_>>().join(", ");
+            if known.is_empty() {
+                anyhow::bail!("Unknown profile `{name}`. There are no profiles in the config.");
             }
+            anyhow::bail!("Unknown profile `{name}`. Known profiles are: {known}");
         };
 
-        if let Ok(existing_config) = std::fs::read_to_string(&path) {
-            if existing_config == config {
-                return Ok(());
+        let origin = Origin::Profile(name);
+        let origins = &mut self.origins;
+        apply(&mut self.model, profile.model, "model", &origin, origins);
+        apply(
+            &mut self.default_temperature,
+            profile.temperature,
+            "default_temperature",
+            &origin,
+            origins,
+        );
+        apply(
+            &mut self.default_frequency_penalty,
+            profile.frequency_penalty,
+            "default_frequency_penalty",
+            &origin,
+            origins,
+        );
+        apply(
+            &mut self.default_number_of_choices,
+            profile.number_of_choices,
+            "default_number_of_choices",
+            &origin,
+            origins,
+        );
+        apply(
+            &mut self.system_msg,
+            profile.system_msg,
+            "system_msg",
+            &origin,
+            origins,
+        );
+        apply(
+            &mut self.api_base,
+            profile.api_base,
+            "api_base",
+            &origin,
+            origins,
+        );
+        apply(
+            &mut self.api_key_env,
+            profile.api_key_env,
+            "api_key_env",
+            &origin,
+            origins,
+        );
+        Ok(self)
+    }
+
+    /// The system message with the prompt additions and scopes of the config.
+    pub fn system_prompt(&self) -> String {
+        let mut prompt = self.system_msg.clone();
+        if !self.prompt_additions.trim().is_empty() {
+            prompt.push_str("\n\n");
+            prompt.push_str(self.prompt_additions.trim());
+        }
+        if !self.scopes.is_empty() {
+            prompt.push_str(&format!(
+                "\n\nIf a scope fits, use one of these scopes: {}",
+                self.scopes.join(", ")
+            ));
+        }
+        prompt
+    }
+
+    /// Prints every field with the layer its value came from.
+    pub fn print_origins(&self) {
+        let Ok(Value::Mapping(values)) = serde_yaml::to_value(self) else {
+            return;
+        };
+        for (key, value) in values {
+            let Some(key) = key.as_str() else {
+                continue;
+            };
+            let value = serde_json::to_string(&value).unwrap_or_default();
+            let value = match value.char_indices().nth(60) {
+                Some((end, _)) => format!("{}...", &value[..end]),
+                None => value,
+            };
+            let origin = self.origins.get(key).cloned().unwrap_or(Origin::Default);
+            println!(
+                "{}: {} {}",
+                key.purple(),
+                value,
+                format!("# {origin}").bright_black()
+            );
+        }
+    }
+
+    /// Writes a config file with the current version and all other defaults commented out,
+    /// if there is none yet. The system message is left out, so it keeps following the
+    /// default of newer versions until it is set.
+    pub fn init(path: &Path) -> anyhow::Result<()> {
+        if path.exists() {
+            anyhow::bail!("{} already exists.", path.display());
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, Self::template()?)?;
+        Ok(())
+    }
+
+    fn template() -> anyhow::Result<String> {
+        let Value::Mapping(defaults) = serde_yaml::to_value(Self::default())? else {
+            anyhow::bail!("Unable to serialize the default config.");
+        };
+        let mut content = format!(
+            "# turbocommit config, see https://github.com/Sett17/turboCommit\n\
+             # Uncomment a setting to change it from its default.\n\
+             version: {}\n",
+            migrate::CURRENT_VERSION
+        );
+        for (key, value) in defaults {
+            if matches!(key.as_str(), Some("version" | "system_msg")) {
+                continue;
+            }
+            let mut entry = Mapping::new();
+            entry.insert(key, value);
+            for line in serde_yaml::to_string(&entry)?.lines() {
+                content.push_str(&format!("# {line}\n"));
             }
         }
+        Ok(content)
+    }
+
+    /// Sets `key` to `value` (parsed as YAML) in the config file at `path`,
+    /// keeping everything else in the file as it is.
+    pub fn set(path: &Path, key: &str, value: &str) -> anyhow::Result<()> {
+        let Ok(Value::Mapping(defaults)) = serde_yaml::to_value(Self::default()) else {
+            anyhow::bail!("Unable to serialize the default config.");
+        };
+        if !defaults.contains_key(key) {
+            let keys = defaults
+                .keys()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            anyhow::bail!("Unknown config key `{key}`. Known keys are: {keys}");
+        }
+        let value = serde_yaml::from_str(value).unwrap_or_else(|_| Value::from(value));
+
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let content = set_key(&content, key, &value)?;
+
+        let mut layer = serde_yaml::from_str::<Option<Mapping>>(&content)?.unwrap_or_default();
+        let mut merged = defaults;
+        merged.extend(std::mem::take(&mut layer));
+        serde_yaml::from_value::<Self>(Value::Mapping(merged))
+            .map_err(|err| anyhow::anyhow!("Invalid value for `{key}`: {err}"))?;
 
-        std::fs::write(path, config)
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, content)?;
+        Ok(())
     }
-    pub fn path() -> std::path::PathBuf {
-        home::home_dir().map_or_else(
-            || {
-                println!("{}", "Unable to find home directory.".red());
-                process::exit(1);
-            },
-            |path| path.join(".turbocommit.yaml"),
+
+    /// `.turbocommit.yaml` in the root of the current repository, if there is one.
+    pub fn repo_path() -> Option<PathBuf> {
+        let repo = git::get_repo().ok()?;
+        Some(repo.workdir()?.join(".turbocommit.yaml"))
+    }
+
+    /// `$XDG_CONFIG_HOME/turbocommit/config.yaml`, falling back to `~/.config`, unless only
+    /// the legacy `~/.turbocommit.yaml` exists.
+    pub fn path() -> anyhow::Result<PathBuf> {
+        let home =
+            home::home_dir().ok_or_else(|| anyhow::anyhow!("Unable to find home directory."))?;
+        let xdg = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .unwrap_or_else(|| home.join(".config"))
+            .join("turbocommit")
+            .join("config.yaml");
+        let legacy = home.join(".turbocommit.yaml");
+        if !xdg.exists() && legacy.exists() {
+            Ok(legacy)
+        } else {
+            Ok(xdg)
+        }
+    }
+}
+
+fn warn_user_only(key: &str, origin: &Origin) {
+    eprintln!(
+        "{}\n{}",
+        format!("`{key}` in the {origin} is ignored, since it decides where the API key is sent.")
+            .red(),
+        "Set it in the user config instead.".bright_black()
+    );
+}
+
+/// The profiles of a repo config, without those that set one of [`USER_ONLY_KEYS`].
+fn without_user_only_keys(profiles: Value, origin: &Origin) -> Value {
+    let Value::Mapping(profiles) = profiles else {
+        return profiles;
+    };
+    let profiles = profiles
+        .into_iter()
+        .filter(|(name, profile)| {
+            let Some(key) = USER_ONLY_KEYS
+                .iter()
+                .find(|key| profile.get(**key).is_some())
+            else {
+                return true;
+            };
+            let name = name.as_str().unwrap_or_default();
+            warn_user_only(&format!("profiles.{name}.{key}"), origin);
+            false
+        })
+        .collect();
+    Value::Mapping(profiles)
+}
+
+/// Sets `field` to the value of a profile, unless an environment variable set it, since those
+/// take precedence over profiles.
+fn apply<T>(
+    field: &mut T,
+    value: Option<T>,
+    key: &str,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    let Some(value) = value else {
+        return;
+    };
+    if matches!(origins.get(key), Some(Origin::Env(_))) {
+        return;
+    }
+    *field = value;
+    origins.insert(key.to_string(), origin.clone());
+}
+
+/// Replaces the top-level `key` in a YAML document, including its indented continuation
+/// lines, or appends it. Comments, including one after the old value, and all other content
+/// stay untouched.
+pub fn set_key(content: &str, key: &str, value: &Value) -> anyhow::Result<String> {
+    let mut entry = Mapping::new();
+    entry.insert(Value::from(key), value.clone());
+    let mut entry = serde_yaml::to_string(&entry)?;
+
+    let lines = content.lines().collect::<Vec<_>>();
+    let span = key_span(&lines, key);
+    if let Some(comment) = span.and_then(|(start, _)| inline_comment(lines[start])) {
+        let end = entry.find('\n').unwrap_or(entry.len());
+        entry.insert_str(end, &format!(" {comment}"));
+    }
+    let Some(span) = span else {
+        let mut content = content.to_string();
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&entry);
+        return Ok(content);
+    };
+    Ok(replace_lines(&lines, span, &entry))
+}
+
+/// Removes the top-level `key` in a YAML document, like [`set_key`] replaces it.
+pub fn remove_key(content: &str, key: &str) -> String {
+    let lines = content.lines().collect::<Vec<_>>();
+    match key_span(&lines, key) {
+        Some(span) => replace_lines(&lines, span, ""),
+        None => content.to_string(),
+    }
+}
+
+/// Renames the top-level key `from` to `to`, keeping its value and comments.
+pub fn rename_key(content: &str, from: &str, to: &str) -> String {
+    let lines = content.lines().collect::<Vec<_>>();
+    let Some((start, _)) = key_span(&lines, from) else {
+        return content.to_string();
+    };
+    let renamed = format!("{to}{}\n", &lines[start][from.len()..]);
+    replace_lines(&lines, (start, start + 1), &renamed)
+}
+
+/// The `# comment` at the end of a YAML line, outside of quotes.
+fn inline_comment(line: &str) -> Option<&str> {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return Some(&line[i..]),
+            None => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+/// The lines `start..end` holding the top-level `key` and its indented continuation lines.
+fn key_span(lines: &[&str], key: &str) -> Option<(usize, usize)> {
+    let start = lines.iter().position(|line| {
+        line.strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with(':'))
+    })?;
+    let mut end = start + 1;
+    while end < lines.len() && (lines[end].trim().is_empty() || lines[end].starts_with([' ', '\t']))
+    {
+        end += 1;
+    }
+    while end > start + 1 && lines[end - 1].trim().is_empty() {
+        end -= 1;
+    }
+    Some((start, end))
+}
+
+fn replace_lines(lines: &[&str], (start, end): (usize, usize), replacement: &str) -> String {
+    let mut result = lines[..start].join("\n");
+    if start > 0 {
+        result.push('\n');
+    }
+    result.push_str(replacement);
+    if end < lines.len() {
+        result.push_str(&lines[end..].join("\n"));
+        result.push('\n');
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(yaml: &str) -> Option<Mapping> {
+        serde_yaml::from_str(yaml).ok()
+    }
+
+    #[test]
+    fn test_layers_override_single_fields() {
+        let user = PathBuf::from("/home/me/.turbocommit.yaml");
+        let repo = PathBuf::from("/repo/.turbocommit.yaml");
+        let config = Config::from_layers(vec![
+            (
+                Origin::User(user.clone()),
+                layer("model: gpt-4\ndefault_number_of_choices: 3"),
+            ),
+            (
+                Origin::Repo(repo.clone()),
+                layer("model: gpt-4o\nscopes: [api, cli]\nunknown: 1"),
+            ),
+            (
+                Origin::Env(String::from("TURBOCOMMIT_DEFAULT_TEMPERATURE")),
+                layer("default_temperature: 0.2"),
+            ),
+        ]);
+
+        assert_eq!(config.model, model::Model::Gpt4o);
+        assert_eq!(config.default_number_of_choices, 3);
+        assert_eq!(config.default_temperature, 0.2);
+        assert_eq!(config.scopes, vec!["api", "cli"]);
+        assert_eq!(config.system_msg, Config::default().system_msg);
+
+        assert_eq!(config.origins["model"], Origin::Repo(repo));
+        assert_eq!(
+            config.origins["default_number_of_choices"],
+            Origin::User(user)
+        );
+        assert_eq!(
+            config.origins["default_temperature"],
+            Origin::Env(String::from("TURBOCOMMIT_DEFAULT_TEMPERATURE"))
+        );
+        assert_eq!(config.origins["system_msg"], Origin::Default);
+        assert!(!config.origins.contains_key("unknown"));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let user = PathBuf::from("/home/me/.turbocommit.yaml");
+        let repo = PathBuf::from("/repo/.turbocommit.yaml");
+        let config = Config::from_layers(vec![
+            (
+                Origin::User(user),
+                layer("profiles:\n  quick: {model: gpt-3.5-turbo, number_of_choices: 1}\n  careful: {model: gpt-4o}"),
+            ),
+            (
+                Origin::Repo(repo),
+                layer("default_profile: release\nprofiles:\n  release: {model: gpt-4o, number_of_choices: 3, temperature: 0.2}"),
+            ),
+        ]);
+        assert_eq!(config.profiles.len(), 3);
+
+        let release = config.clone().with_profile(None).unwrap();
+        assert_eq!(release.model, model::Model::Gpt4o);
+        assert_eq!(release.default_number_of_choices, 3);
+        assert_eq!(release.default_temperature, 0.2);
+        assert_eq!(
+            release.default_frequency_penalty,
+            config.default_frequency_penalty
+        );
+        assert_eq!(
+            release.origins["model"],
+            Origin::Profile(String::from("release"))
+        );
+
+        let quick = config.clone().with_profile(Some("quick")).unwrap();
+        assert_eq!(quick.default_number_of_choices, 1);
+        assert_eq!(quick.default_temperature, config.default_temperature);
+
+        assert!(config.clone().with_profile(Some("missing")).is_err());
+
+        let mut from_env = config;
+        from_env.default_number_of_choices = 5;
+        from_env.origins.insert(
+            String::from("default_number_of_choices"),
+            Origin::Env(String::from("TURBOCOMMIT_DEFAULT_NUMBER_OF_CHOICES")),
+        );
+        let release = from_env.with_profile(None).unwrap();
+        assert_eq!(release.default_number_of_choices, 5);
+        assert_eq!(release.model, model::Model::Gpt4o);
+    }
+
+    #[test]
+    fn test_invalid_value_is_ignored_alone() {
+        let repo = PathBuf::from("/repo/.turbocommit.yaml");
+        let config = Config::from_layers(vec![
+            (
+                Origin::User(PathBuf::from("/home/me/.turbocommit.yaml")),
+                layer("model: gpt-4o\ndaily_budget: 2.0"),
+            ),
+            (
+                Origin::Repo(repo.clone()),
+                layer("default_number_of_choices: many\ndefault_temperature: 0.3"),
+            ),
+        ]);
+        assert_eq!(config.model, model::Model::Gpt4o);
+        assert_eq!(config.daily_budget, Some(2.0));
+        assert_eq!(config.default_temperature, 0.3);
+        assert_eq!(
+            config.default_number_of_choices,
+            Config::default().default_number_of_choices
+        );
+        assert_eq!(config.origins["default_number_of_choices"], Origin::Default);
+        assert_eq!(config.origins["default_temperature"], Origin::Repo(repo));
+    }
+
+    #[test]
+    fn test_repo_cannot_redirect_the_api_key() {
+        let config = Config::from_layers(vec![
+            (
+                Origin::User(PathBuf::from("/home/me/.turbocommit.yaml")),
+                layer("profiles:\n  local:\n    api_base: http://localhost:8080/v1"),
+            ),
+            (
+                Origin::Repo(PathBuf::from("/repo/.turbocommit.yaml")),
+                layer(
+                    "api_base: https://evil.example\napi_key_env: GITHUB_TOKEN\nmodel: gpt-4o
+profiles:
+  evil:
+    api_key_env: GITHUB_TOKEN
+  quick:
+    number_of_choices: 1
+default_profile: evil",
+                ),
+            ),
+        ]);
+        let defaults = Config::default();
+        assert_eq!(config.api_base, defaults.api_base);
+        assert_eq!(config.api_key_env, defaults.api_key_env);
+        assert_eq!(config.origins["api_base"], Origin::Default);
+        assert_eq!(config.model, model::Model::Gpt4o);
+        assert_eq!(
+            config.profiles.keys().collect::<Vec<_>>(),
+            ["local", "quick"]
+        );
+        assert!(config.clone().with_profile(None).is_err());
+        let config = config.with_profile(Some("local")).unwrap();
+        assert_eq!(config.api_base, "http://localhost:8080/v1");
+    }
+
+    #[test]
+    fn test_set_key_keeps_comments_and_unknown_keys() {
+        let content = "# my config\nmodel: gpt-4 # the good one\nsystem_msg: |\n  line one\n\n  line two\n\n# custom\nunknown: 1\n";
+
+        let updated = set_key(content, "system_msg", &Value::from("short")).unwrap();
+        assert_eq!(
+            updated,
+            "# my config\nmodel: gpt-4 # the good one\nsystem_msg: short\n\n# custom\nunknown: 1\n"
+        );
+
+        let updated = set_key(&updated, "model", &Value::from("gpt-4o")).unwrap();
+        assert!(
+            updated.starts_with("# my config\nmodel: gpt-4o # the good one\nsystem_msg: short\n")
+        );
+
+        let updated = set_key(&updated, "scopes", &serde_yaml::from_str("[api]").unwrap()).unwrap();
+        assert!(updated.ends_with("unknown: 1\nscopes:\n- api\n"));
+
+        let updated = rename_key(&updated, "unknown", "known");
+        assert!(updated.contains("# custom\nknown: 1\n"));
+
+        let updated = remove_key(&updated, "system_msg");
+        assert_eq!(
+            updated,
+            "# my config\nmodel: gpt-4o # the good one\n\n# custom\nknown: 1\nscopes:\n- api\n"
+        );
+    }
+
+    #[test]
+    fn test_set_key_keeps_inline_comments() {
+        let content = "api_base: 'http://a#b' # proxy\nscopes: [api] # shared\nsignoff: true\n";
+        let updated = set_key(content, "api_base", &Value::from("http://c")).unwrap();
+        assert!(updated.starts_with("api_base: http://c # proxy\n"));
+        let updated = set_key(
+            &updated,
+            "scopes",
+            &serde_yaml::from_str("[api, cli]").unwrap(),
         )
+        .unwrap();
+        assert!(updated.contains("scopes: # shared\n- api\n- cli\n"));
+        let updated = set_key(&updated, "signoff", &Value::from(false)).unwrap();
+        assert!(updated.ends_with("signoff: false\n"));
+        let layer = serde_yaml::from_str::<Mapping>(&updated).unwrap();
+        assert_eq!(
+            layer["scopes"],
+            serde_yaml::from_str::<Value>("[api, cli]").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_init_template() {
+        let template = Config::template().unwrap();
+        let layer = serde_yaml::from_str::<Mapping>(&template).unwrap();
+        assert_eq!(layer.len(), 1);
+        assert_eq!(layer["version"], Value::from(migrate::CURRENT_VERSION));
+        assert!(!template.contains("system_msg"));
+        assert!(template.contains("\n# model: gpt-3.5-turbo\n"));
+    }
+
+    #[test]
+    fn test_system_prompt_includes_additions_and_scopes() {
+        let config = Config {
+            prompt_additions: String::from("Write in British English."),
+            scopes: vec![String::from("api")],
+            ..Default::default()
+        };
+        let prompt = config.system_prompt();
+
+        assert!(prompt.starts_with(&config.system_msg));
+        assert!(prompt.contains("Write in British English."));
+        assert!(prompt.contains("use one of these scopes: api"));
     }
 }
@@ -0,0 +1,264 @@
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+use git2::Repository;
+
+const SCISSORS: &str = "------------------------ >8 ------------------------";
+
+/// How comments and whitespace are removed from an edited message, like `commit.cleanup`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cleanup {
+    Strip,
+    Whitespace,
+    Verbatim,
+}
+
+impl Cleanup {
+    /// The mode for an edited message. The scissors line is always honored.
+    fn from_config(mode: Option<&str>) -> Self {
+        match mode {
+            Some("whitespace" | "scissors") => Self::Whitespace,
+            Some("verbatim") => Self::Verbatim,
+            _ => Self::Strip,
+        }
+    }
+}
+
+/// What to show below the scissors line when editing a message.
+#[derive(Debug, Default)]
+pub struct Context {
+    pub instruction: String,
+    pub stat: String,
+}
+
+/// The editor git would use: `GIT_EDITOR`, `core.editor`, `VISUAL`, `EDITOR`, then `vi`.
+pub fn command(repo: Option<&Repository>) -> anyhow::Result<String> {
+    let core_editor = repo
+        .and_then(|repo| repo.config().ok())
+        .and_then(|config| config.get_string("core.editor").ok());
+    resolve(
+        env::var("GIT_EDITOR").ok(),
+        core_editor,
+        env::var("VISUAL").ok(),
+        env::var("EDITOR").ok(),
+        env::var("TERM").ok().as_deref() == Some("dumb"),
+    )
+    .ok_or_else(|| {
+        anyhow::anyhow!(
+            "Terminal is dumb, but EDITOR is unset. Please set GIT_EDITOR or core.editor."
+        )
+    })
+}
+
+fn resolve(
+    git_editor: Option<String>,
+    core_editor: Option<String>,
+    visual: Option<String>,
+    editor: Option<String>,
+    dumb: bool,
+) -> Option<String> {
+    let visual = visual.filter(|_| !dumb);
+    git_editor
+        .into_iter()
+        .chain(core_editor)
+        .chain(visual)
+        .chain(editor)
+        .find(|editor| !editor.trim().is_empty())
+        .or_else(|| (!dumb).then(|| String::from("vi")))
+}
+
+/// `core.commentChar`, where `auto` picks one that no line of `message` starts with.
+pub fn comment_char(repo: Option<&Repository>, message: &str) -> char {
+    let configured = repo
+        .and_then(|repo| repo.config().ok())
+        .and_then(|config| config.get_string("core.commentChar").ok());
+    match configured.as_deref() {
+        Some("auto") => "#;@!$%^&|:"
+            .chars()
+            .find(|c| !message.lines().any(|line| line.starts_with(*c)))
+            .unwrap_or('#'),
+        Some(chars) => chars.chars().next().unwrap_or('#'),
+        None => '#',
+    }
+}
+
+/// The content of `commit.template`, if it is set and readable.
+pub fn template(repo: &Repository) -> Option<String> {
+    let path = repo.config().ok()?.get_path("commit.template").ok()?;
+    let path = if path.is_relative() {
+        repo.workdir().unwrap_or_else(|| repo.path()).join(path)
+    } else {
+        path
+    };
+    std::fs::read_to_string(path)
+        .ok()
+        .filter(|template| !template.trim().is_empty())
+}
+
+/// Opens `message` in the editor git would use, and returns it cleaned up like
+/// `commit.cleanup` says, without everything below the scissors line.
+pub fn edit(message: &str, context: &Context) -> anyhow::Result<String> {
+    let repo = crate::git::get_repo().ok();
+    let comment = comment_char(repo.as_ref(), message);
+    let template_comments = repo
+        .as_ref()
+        .and_then(template)
+        .map(|template| {
+            template
+                .lines()
+                .filter(|line| line.starts_with(comment))
+                .map(|line| format!("{line}\n"))
+                .collect::<String>()
+        })
+        .unwrap_or_default();
+    let content = format!(
+        "{}\n\n{template_comments}{}",
+        message.trim_end(),
+        below_scissors(context, comment)
+    );
+
+    let dir = repo
+        .as_ref()
+        .map_or_else(env::temp_dir, |repo| repo.path().to_path_buf());
+    let path = dir.join("TURBOCOMMIT_EDITMSG");
+    std::fs::write(&path, content)?;
+    let command = command(repo.as_ref())?;
+    let status = crate::interrupt::ignored(|| run(&command, &path))?;
+    let edited = std::fs::read_to_string(&path)?;
+    let _ = std::fs::remove_file(&path);
+    if !status.success() {
+        anyhow::bail!("The editor exited with {status}.");
+    }
+
+    let mode = repo
+        .as_ref()
+        .and_then(|repo| repo.config().ok())
+        .and_then(|config| config.get_string("commit.cleanup").ok());
+    Ok(cleanup(
+        &edited,
+        Cleanup::from_config(mode.as_deref()),
+        comment,
+    ))
+}
+
+fn below_scissors(context: &Context, comment: char) -> String {
+    let mut lines = vec![
+        format!("{comment} {SCISSORS}"),
+        format!("{comment} Do not modify or remove the line above."),
+        format!("{comment} Everything below it will be ignored."),
+    ];
+    if !context.instruction.trim().is_empty() {
+        lines.push(format!("{comment}"));
+        lines.push(format!(
+            "{comment} Instruction: {}",
+            context.instruction.trim()
+        ));
+    }
+    if !context.stat.trim().is_empty() {
+        lines.push(format!("{comment}"));
+        lines.extend(
+            context
+                .stat
+                .lines()
+                .map(|line| format!("{comment} {line}").trim_end().to_string()),
+        );
+    }
+    lines.join("\n") + "\n"
+}
+
+fn run(editor: &str, path: &Path) -> std::io::Result<std::process::ExitStatus> {
+    if cfg!(windows) {
+        Command::new("cmd")
+            .arg("/C")
+            .arg(format!("{editor} \"{}\"", path.display()))
+            .status()
+    } else {
+        Command::new("sh")
+            .arg("-c")
+            .arg(format!("{editor} \"$@\""))
+            .arg(editor)
+            .arg(path)
+            .status()
+    }
+}
+
+/// Cuts `message` at the scissors line, then removes comments (for [`Cleanup::Strip`]),
+/// trailing whitespace, and leading, trailing and repeated blank lines.
+pub fn cleanup(message: &str, mode: Cleanup, comment: char) -> String {
+    let scissors = format!("{comment} {SCISSORS}");
+    let message = message
+        .lines()
+        .take_while(|line| *line != scissors)
+        .collect::<Vec<_>>();
+    if mode == Cleanup::Verbatim {
+        return message.join("\n");
+    }
+
+    let mut result: Vec<&str> = Vec::new();
+    for line in message {
+        if mode == Cleanup::Strip && line.starts_with(comment) {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() && result.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        result.push(line);
+    }
+    while result.last().is_some_and(|line| line.is_empty()) {
+        result.pop();
+    }
+    result.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn some(value: &str) -> Option<String> {
+        Some(value.to_string())
+    }
+
+    #[test]
+    fn test_resolve_follows_git() {
+        assert_eq!(
+            resolve(some("nano"), some("vim"), some("code -w"), None, false),
+            some("nano")
+        );
+        assert_eq!(
+            resolve(None, some("vim"), some("code -w"), some("ed"), false),
+            some("vim")
+        );
+        assert_eq!(
+            resolve(None, None, some("code -w"), some("ed"), true),
+            some("ed")
+        );
+        assert_eq!(resolve(None, None, None, None, false), some("vi"));
+        assert_eq!(resolve(None, None, some("code -w"), None, true), None);
+    }
+
+    #[test]
+    fn test_cleanup() {
+        let context = Context {
+            instruction: String::from("be brief"),
+            stat: String::from(" a.txt | 2 ++\n 1 file changed"),
+        };
+        let edited = format!(
+            "\n\nfeat: add login  \n\n\n# a comment\nBody.\n\n{}",
+            below_scissors(&context, '#')
+        );
+        assert_eq!(
+            cleanup(&edited, Cleanup::Strip, '#'),
+            "feat: add login\n\nBody."
+        );
+        assert_eq!(
+            cleanup(&edited, Cleanup::Whitespace, '#'),
+            "feat: add login\n\n# a comment\nBody."
+        );
+        assert_eq!(
+            cleanup("fix: x\n; note\n#123 stays", Cleanup::Strip, ';'),
+            "fix: x\n#123 stays"
+        );
+    }
+}
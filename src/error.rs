@@ -0,0 +1,103 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Failures that scripts may want to tell apart by exit code.
+/// Anything else exits with 1, and invalid arguments exit with 2.
+#[derive(Debug)]
+pub enum Failure {
+    NoStagedChanges,
+    TooLarge { tokens: usize, max: usize },
+    Api(ApiError),
+    Interrupted,
+    OverBudget(String),
+}
+
+/// Why a request to the API failed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ApiError {
+    InvalidKey,
+    QuotaExceeded,
+    RateLimited,
+    ContextLengthExceeded,
+    ModelNotFound(String),
+    Timeout(Duration),
+    Other {
+        status: Option<u16>,
+        message: String,
+    },
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidKey => write!(
+                f,
+                "The API key was rejected. Check the key in OPENAI_API_KEY, or in the variable named by `api_key_env`."
+            ),
+            Self::QuotaExceeded => write!(
+                f,
+                "You exceeded your API quota. Check your plan and billing details at https://platform.openai.com/account/billing."
+            ),
+            Self::RateLimited => write!(
+                f,
+                "The API rate limit was reached. Wait a moment and try again, or ask for fewer choices with -n."
+            ),
+            Self::ContextLengthExceeded => write!(
+                f,
+                "The request is too long for the model. Leave out some files, or pick a model with a larger context window with -m."
+            ),
+            Self::ModelNotFound(model) => write!(
+                f,
+                "The model {model} does not exist or your API key has no access to it. Pick another one with -m."
+            ),
+            Self::Timeout(timeout) => write!(
+                f,
+                "There was no complete response within {}s. Try again later, or raise `request_timeout` in the config.",
+                timeout.as_secs()
+            ),
+            Self::Other {
+                status: Some(status),
+                message,
+            } => write!(f, "The API responded with {status}: {message}"),
+            Self::Other {
+                status: None,
+                message,
+            } => write!(f, "The API request failed: {message}"),
+        }
+    }
+}
+
+impl Failure {
+    pub const fn exit_code(&self) -> u8 {
+        match self {
+            Self::NoStagedChanges => 3,
+            Self::TooLarge { .. } => 4,
+            Self::Api(_) => 5,
+            Self::OverBudget(_) => 6,
+            Self::Interrupted => 130,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoStagedChanges => write!(
+                f,
+                "No staged files. Please stage the files you want to commit."
+            ),
+            Self::TooLarge { tokens, max } => write!(
+                f,
+                "The request is ~{tokens} tokens long, while the maximum is {max}."
+            ),
+            Self::Api(err) => write!(f, "{err}"),
+            Self::Interrupted => write!(f, "Interrupted with Ctrl-C."),
+            Self::OverBudget(message) => write!(
+                f,
+                "{message} Requests are blocked, because `budget_action` is `block`."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
@@ -1,39 +1,141 @@
-use git2::{Repository, Tree};
+use git2::{
+    DescribeFormatOptions, DescribeOptions, Diff, DiffOptions, Pathspec, PathspecFlags, Repository,
+    Sort, Tree,
+};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 pub fn get_repo() -> Result<Repository, git2::Error> {
     Repository::discover(".")
 }
 
-pub fn staged_files(repo: &Repository) -> Result<Vec<String>, git2::Error> {
+pub fn staged(repo: &Repository) -> Result<Diff<'_>, git2::Error> {
     let idx = repo.index()?;
     let mut head: Option<Tree> = None;
     if let Ok(h) = repo.head() {
         head = Some(h.peel_to_tree()?);
     }
-    let diff = repo.diff_tree_to_index(head.as_ref(), Some(&idx), None)?;
-    Ok(diff
-        .deltas()
+    repo.diff_tree_to_index(head.as_ref(), Some(&idx), None)
+}
+
+/// Changes of the working tree against HEAD, staged or not, like `git commit -a` would
+/// commit them. Untracked files are only included with `include_untracked`.
+pub fn working_tree(repo: &Repository, include_untracked: bool) -> Result<Diff<'_>, git2::Error> {
+    let mut head: Option<Tree> = None;
+    if let Ok(h) = repo.head() {
+        head = Some(h.peel_to_tree()?);
+    }
+    let mut opts = DiffOptions::new();
+    opts.include_untracked(include_untracked)
+        .recurse_untracked_dirs(include_untracked)
+        .show_untracked_content(include_untracked);
+    repo.diff_tree_to_workdir_with_index(head.as_ref(), Some(&mut opts))
+}
+
+/// Changes of `files` in the working tree against HEAD, as they would be committed after
+/// staging them, untracked ones included.
+pub fn selection<'a>(repo: &'a Repository, files: &[String]) -> Result<Diff<'a>, git2::Error> {
+    let mut head: Option<Tree> = None;
+    if let Ok(h) = repo.head() {
+        head = Some(h.peel_to_tree()?);
+    }
+    let mut opts = DiffOptions::new();
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true)
+        .disable_pathspec_match(true);
+    for file in files {
+        opts.pathspec(file);
+    }
+    repo.diff_tree_to_workdir_with_index(head.as_ref(), Some(&mut opts))
+}
+
+/// Adds `files` to the index, or removes them if they were deleted.
+pub fn stage(repo: &Repository, files: &[String]) -> Result<(), git2::Error> {
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| git2::Error::from_str("cannot stage files in a bare repository"))?;
+    let mut index = repo.index()?;
+    for file in files {
+        let path = Path::new(file);
+        if workdir.join(path).exists() {
+            index.add_path(path)?;
+        } else {
+            index.remove_path(path)?;
+        }
+    }
+    index.write()
+}
+
+/// Parses a patch, like the output of `git diff` or `git format-patch`.
+pub fn parse_patch(patch: &[u8]) -> Result<Diff<'static>, git2::Error> {
+    Diff::from_buffer(patch)
+}
+
+pub fn files(diff: &Diff) -> Vec<String> {
+    diff.deltas()
         .map(|d| {
             let path = d.new_file().path();
             path.map_or_else(String::new, |path| path.to_str().unwrap_or("").to_string())
         })
+        .collect()
+}
+
+/// Removes the files matching any of the `ignore` pathspecs.
+pub fn without_ignored(files: Vec<String>, ignore: &[String]) -> Result<Vec<String>, git2::Error> {
+    if ignore.is_empty() {
+        return Ok(files);
+    }
+    let pathspec = Pathspec::new(ignore)?;
+    Ok(files
+        .into_iter()
+        .filter(|file| !pathspec.matches_path(Path::new(file), PathspecFlags::DEFAULT))
         .collect())
 }
 
-pub fn diff(repo: &Repository, files: &[String]) -> Result<String, git2::Error> {
-    let mut ret = String::new();
+/// The `git diff --stat` of `files` in `diff`.
+pub fn stat(diff: &Diff, files: &[String]) -> Result<String, git2::Error> {
+    let mut stats = Vec::new();
+    for (i, delta) in diff.deltas().enumerate() {
+        let path = delta.new_file().path().and_then(Path::to_str).unwrap_or("");
+        if !files.iter().any(|file| file == path) {
+            continue;
+        }
+        let Some(patch) = git2::Patch::from_diff(diff, i)? else {
+            continue;
+        };
+        let (_, insertions, deletions) = patch.line_stats()?;
+        stats.push((path.to_string(), insertions, deletions));
+    }
 
-    let idx = repo.index()?;
-    let mut head: Option<Tree> = None;
-    if let Ok(h) = repo.head() {
-        head = Some(h.peel_to_tree()?);
+    let width = stats.iter().map(|(path, ..)| path.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for (path, insertions, deletions) in &stats {
+        out.push_str(&format!(
+            " {path:width$} | {} {}{}\n",
+            insertions + deletions,
+            "+".repeat((*insertions).min(40)),
+            "-".repeat((*deletions).min(40))
+        ));
     }
-    let diff = repo.diff_tree_to_index(head.as_ref(), Some(&idx), None)?;
+    let insertions = stats.iter().map(|(_, i, _)| i).sum::<usize>();
+    let deletions = stats.iter().map(|(_, _, d)| d).sum::<usize>();
+    out.push_str(&format!(
+        " {} file{} changed, {insertions} insertions(+), {deletions} deletions(-)",
+        stats.len(),
+        if stats.len() == 1 { "" } else { "s" }
+    ));
+    Ok(out)
+}
+
+pub fn patch(diff: &Diff, files: &[String]) -> Result<String, git2::Error> {
+    let mut ret = String::new();
     diff.print(git2::DiffFormat::Patch, |delta, _, line| {
         if let Some(path) = delta.new_file().path() {
             if files.contains(&path.to_str().unwrap_or("").to_string()) {
-                ret.push(line.origin());
+                if let '+' | '-' | ' ' = line.origin() {
+                    ret.push(line.origin());
+                }
                 ret.push_str(std::str::from_utf8(line.content()).unwrap_or(""));
             }
         }
@@ -42,6 +144,118 @@ pub fn diff(repo: &Repository, files: &[String]) -> Result<String, git2::Error>
     Ok(ret)
 }
 
+/// Returns `main` or `master`, whichever exists locally.
+pub fn default_branch(repo: &Repository) -> Option<String> {
+    ["main", "master"]
+        .iter()
+        .find(|name| repo.find_branch(name, git2::BranchType::Local).is_ok())
+        .map(ToString::to_string)
+}
+
+/// Diff of HEAD against its merge base with `base`, like `git diff base...HEAD`.
+pub fn branch_diff(repo: &Repository, base: &str) -> Result<String, git2::Error> {
+    let head = repo.head()?.peel_to_commit()?;
+    let base = repo.revparse_single(base)?.peel_to_commit()?;
+    let merge_base = repo.find_commit(repo.merge_base(base.id(), head.id())?)?;
+    let diff = repo.diff_tree_to_tree(Some(&merge_base.tree()?), Some(&head.tree()?), None)?;
+    patch(&diff, &files(&diff))
+}
+
+pub fn latest_tag(repo: &Repository) -> Option<String> {
+    let describe = repo.describe(DescribeOptions::new().describe_tags()).ok()?;
+    describe
+        .format(Some(DescribeFormatOptions::new().abbreviated_size(0)))
+        .ok()
+}
+
+/// Full messages of the commits in `range` (e.g. `v1.0.0..HEAD`), newest first.
+/// Without a range, all commits reachable from HEAD are returned.
+pub fn log(repo: &Repository, range: Option<&str>) -> Result<Vec<String>, git2::Error> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    match range {
+        Some(range) if range.contains("..") => walk.push_range(range)?,
+        Some(rev) => walk.push(repo.revparse_single(rev)?.peel_to_commit()?.id())?,
+        None => walk.push_head()?,
+    }
+    walk.map(|oid| {
+        let commit = repo.find_commit(oid?)?;
+        Ok(commit.message().unwrap_or("").trim().to_string())
+    })
+    .collect()
+}
+
+/// Name of the checked out branch, `None` on a detached HEAD.
+pub fn current_branch(repo: &Repository) -> Option<String> {
+    let head = repo.head().ok()?;
+    if !head.is_branch() {
+        return None;
+    }
+    head.shorthand().map(String::from)
+}
+
+/// Summaries of the last `n` commits on HEAD, newest first.
+pub fn recent_summaries(repo: &Repository, n: usize) -> Vec<String> {
+    let Ok(mut walk) = repo.revwalk() else {
+        return Vec::new();
+    };
+    if walk.set_sorting(Sort::TIME).is_err() || walk.push_head().is_err() {
+        return Vec::new();
+    }
+    walk.filter_map(Result::ok)
+        .filter_map(|oid| repo.find_commit(oid).ok())
+        .filter_map(|commit| commit.summary().map(String::from))
+        .take(n)
+        .collect()
+}
+
+/// `Name <email>` of the configured git identity.
+pub fn identity(repo: &Repository) -> Result<String, git2::Error> {
+    let signature = repo.signature()?;
+    Ok(format!(
+        "{} <{}>",
+        signature.name().unwrap_or_default(),
+        signature.email().unwrap_or_default()
+    ))
+}
+
+/// `Name <email>` of the authors of the last `n` commits on HEAD, most recent first,
+/// without duplicates.
+pub fn recent_authors(repo: &Repository, n: usize) -> Vec<String> {
+    let Ok(mut walk) = repo.revwalk() else {
+        return Vec::new();
+    };
+    if walk.set_sorting(Sort::TIME).is_err() || walk.push_head().is_err() {
+        return Vec::new();
+    }
+    let mut authors = Vec::new();
+    for commit in walk
+        .filter_map(Result::ok)
+        .filter_map(|oid| repo.find_commit(oid).ok())
+        .take(n)
+    {
+        let author = commit.author();
+        let author = format!(
+            "{} <{}>",
+            author.name().unwrap_or_default(),
+            author.email().unwrap_or_default()
+        );
+        if !authors.contains(&author) {
+            authors.push(author);
+        }
+    }
+    authors
+}
+
+pub fn hooks_dir(repo: &Repository) -> Result<PathBuf, git2::Error> {
+    let config = repo.config()?;
+    match config.get_path("core.hooksPath") {
+        Ok(path) if path.is_absolute() => Ok(path),
+        Ok(path) => Ok(repo.workdir().unwrap_or_else(|| repo.path()).join(path)),
+        Err(_) => Ok(repo.path().join("hooks")),
+    }
+}
+
 // idk how this is really supposed to work
 // pub fn commit(repo: &Repository, files: &[String], msg: &str) -> Result<(), git2::Error> {
 //     let mut index = repo.index()?;
@@ -60,11 +274,64 @@ pub fn diff(repo: &Repository, files: &[String]) -> Result<String, git2::Error>
 //     Ok(())
 // }
 
-pub fn commit(msg: String) -> anyhow::Result<()> {
-    Command::new("git")
+/// Whether to sign a commit, on top of what `commit.gpgsign` says.
+/// `git commit` itself takes care of `user.signingkey` and `gpg.format`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Signing {
+    #[default]
+    Config,
+    /// Sign, with the given key instead of `user.signingkey` if there is one.
+    Sign(Option<String>),
+    Off,
+}
+
+impl Signing {
+    fn arg(&self) -> Option<String> {
+        match self {
+            Self::Config => None,
+            Self::Sign(None) => Some(String::from("--gpg-sign")),
+            Self::Sign(Some(key)) => Some(format!("--gpg-sign={key}")),
+            Self::Off => Some(String::from("--no-gpg-sign")),
+        }
+    }
+}
+
+/// Runs `git commit` attached to the terminal, so gpg or ssh can ask for a passphrase.
+pub fn commit(msg: String, signing: &Signing) -> anyhow::Result<()> {
+    let status = Command::new("git")
         .arg("commit")
+        .args(signing.arg())
         .arg("-m")
-        .arg(msg)
-        .output()?;
+        .arg(&msg)
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("git commit failed ({status}). The message was:\n\n{msg}");
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "diff --git a/a.txt b/a.txt
+index 7898192..6178079 100644
+--- a/a.txt
++++ b/a.txt
+@@ -1,2 +1,2 @@
+ a
+-b
++c
+\\ No newline at end of file
+";
+
+    #[test]
+    fn test_patch_keeps_headers_unprefixed() {
+        let diff = parse_patch(PATCH.as_bytes()).unwrap();
+        let patch = patch(&diff, &files(&diff)).unwrap();
+        // only content lines get their origin, file and hunk headers already start with theirs
+        assert!(patch.starts_with("diff --git a/a.txt b/a.txt\n"));
+        assert!(patch.contains("\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n"));
+        assert!(patch.ends_with("\n\\ No newline at end of file\n"));
+    }
+}
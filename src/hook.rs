@@ -0,0 +1,75 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+use colored::Colorize;
+use git2::Repository;
+
+use crate::git;
+
+const MARKER: &str = "# installed by turbocommit";
+
+const SCRIPT: &str = "#!/bin/sh
+# installed by turbocommit
+# Generates a commit message when running a plain `git commit`.
+if [ -t 1 ] && [ -r /dev/tty ]; then
+    exec turbocommit hook run \"$@\" < /dev/tty
+fi
+";
+
+fn path(repo: &Repository) -> anyhow::Result<PathBuf> {
+    Ok(git::hooks_dir(repo)?.join("prepare-commit-msg"))
+}
+
+fn is_ours(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|content| content.contains(MARKER))
+}
+
+pub fn install(repo: &Repository) -> anyhow::Result<()> {
+    let path = path(repo)?;
+    if path.exists() && !is_ours(&path) {
+        bail!(
+            "A prepare-commit-msg hook already exists at {}. Remove it first.",
+            path.display()
+        );
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, SCRIPT)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+    println!(
+        "{} {}",
+        "Hook installed!".purple(),
+        path.display().to_string().bright_black()
+    );
+    Ok(())
+}
+
+pub fn uninstall(repo: &Repository) -> anyhow::Result<()> {
+    let path = path(repo)?;
+    if !path.exists() {
+        println!("{}", "No hook installed.".bright_black());
+        return Ok(());
+    }
+    if !is_ours(&path) {
+        bail!(
+            "The prepare-commit-msg hook at {} was not installed by turbocommit.",
+            path.display()
+        );
+    }
+    fs::remove_file(&path)?;
+    println!("{}", "Hook removed.".purple());
+    Ok(())
+}
+
+/// Writes `message` above the content git already put into the message file.
+pub fn write_message(file: &Path, message: &str) -> anyhow::Result<()> {
+    let existing = fs::read_to_string(file).unwrap_or_default();
+    fs::write(file, format!("{}\n{}", message.trim_end(), existing))?;
+    Ok(())
+}
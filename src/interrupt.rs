@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Once;
+
+use crossterm::cursor::Show;
+use crossterm::style::ResetColor;
+use crossterm::{execute, terminal};
+use tokio::sync::Notify;
+
+use crate::error::Failure;
+
+// Once tokio listens for SIGINT, the default of terminating the process is gone for good,
+// so a single listener decides what Ctrl-C means at the moment.
+const EXIT: u8 = 0;
+const CANCEL: u8 = 1;
+const IGNORE: u8 = 2;
+
+static MODE: AtomicU8 = AtomicU8::new(EXIT);
+static CANCELLED: Notify = Notify::const_new();
+static LISTEN: Once = Once::new();
+
+/// Sets what Ctrl-C does until it is dropped, then restores what it did before.
+struct Scope(u8);
+
+impl Scope {
+    fn enter(mode: u8) -> Self {
+        Self(MODE.swap(mode, Ordering::SeqCst))
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        MODE.store(self.0, Ordering::SeqCst);
+    }
+}
+
+fn listen() {
+    LISTEN.call_once(|| {
+        tokio::spawn(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                match MODE.load(Ordering::SeqCst) {
+                    CANCEL => CANCELLED.notify_waiters(),
+                    IGNORE => {}
+                    _ => {
+                        // what the default handler would do, without leaving a prompt's raw
+                        // mode or a hidden cursor behind
+                        let _ = terminal::disable_raw_mode();
+                        let _ = execute!(std::io::stdout(), ResetColor, Show);
+                        eprintln!();
+                        std::process::exit(i32::from(Failure::Interrupted.exit_code()));
+                    }
+                }
+            }
+        });
+    });
+}
+
+/// Completes when Ctrl-C is pressed while it is awaited, instead of exiting.
+pub async fn cancelled() {
+    listen();
+    let notified = CANCELLED.notified();
+    let _scope = Scope::enter(CANCEL);
+    notified.await;
+}
+
+/// Runs `f` with Ctrl-C ignored, like git does while the editor runs, so the editor can
+/// use it and is not left behind in the terminal.
+pub fn ignored<T>(f: impl FnOnce() -> T) -> T {
+    let _scope = Scope::enter(IGNORE);
+    f()
+}
+
+/// Turns Ctrl-C in a prompt into [`Failure::Interrupted`], like Ctrl-C anywhere else.
+pub fn from_prompt(err: anyhow::Error) -> anyhow::Error {
+    match err.downcast_ref::<inquire::InquireError>() {
+        Some(inquire::InquireError::OperationInterrupted) => Failure::Interrupted.into(),
+        _ => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes_restore_the_mode() {
+        assert_eq!(MODE.load(Ordering::SeqCst), EXIT);
+        ignored(|| {
+            assert_eq!(MODE.load(Ordering::SeqCst), IGNORE);
+            let scope = Scope::enter(CANCEL);
+            assert_eq!(MODE.load(Ordering::SeqCst), CANCEL);
+            drop(scope);
+            assert_eq!(MODE.load(Ordering::SeqCst), IGNORE);
+        });
+        assert_eq!(MODE.load(Ordering::SeqCst), EXIT);
+
+        let err = from_prompt(inquire::InquireError::OperationInterrupted.into());
+        assert!(matches!(
+            err.downcast_ref::<Failure>(),
+            Some(Failure::Interrupted)
+        ));
+        let err = from_prompt(inquire::InquireError::OperationCanceled.into());
+        assert!(err.downcast_ref::<Failure>().is_none());
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Datelike, NaiveDate, SubsecRound, Utc};
+use clap::ValueEnum;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Failure;
+
+/// One request to the API, as a line of the ledger.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: DateTime<Utc>,
+    pub model: String,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    /// In USD, from [`Model::cost`](crate::model::Model::cost).
+    pub cost: f64,
+    /// The work tree of the repository the request was made in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// Whether the API did not report the usage, so it was counted locally.
+    #[serde(default)]
+    pub estimated: bool,
+}
+
+impl Entry {
+    pub fn new(
+        model: String,
+        prompt_tokens: usize,
+        completion_tokens: usize,
+        cost: f64,
+        estimated: bool,
+    ) -> Self {
+        let repo = crate::git::get_repo().ok().and_then(|repo| {
+            repo.workdir()
+                .map(|dir| dir.components().as_path().display().to_string())
+        });
+        Self {
+            timestamp: now(),
+            model,
+            prompt_tokens,
+            completion_tokens,
+            cost,
+            repo,
+            estimated,
+        }
+    }
+}
+
+fn now() -> DateTime<Utc> {
+    DateTime::<Utc>::from(SystemTime::now()).trunc_subsecs(0)
+}
+
+/// `$XDG_DATA_HOME/turbocommit/usage.jsonl`, usually `~/.local/share/turbocommit/usage.jsonl`.
+pub fn path() -> anyhow::Result<PathBuf> {
+    let dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home::home_dir().map(|home| home.join(".local").join("share")))
+        .ok_or_else(|| anyhow::anyhow!("Unable to find home directory."))?;
+    Ok(dir.join("turbocommit").join("usage.jsonl"))
+}
+
+/// Appends `entry` to the ledger.
+pub fn record(entry: &Entry) -> anyhow::Result<()> {
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// All entries of the ledger. Lines that cannot be parsed, like one cut off by a crash,
+/// are skipped.
+pub fn read() -> anyhow::Result<Vec<Entry>> {
+    let content = match fs::read_to_string(path()?) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// What happens when a budget is used up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetAction {
+    /// Print a warning, and send the request anyway.
+    #[default]
+    Warn,
+    /// Fail without sending the request.
+    Block,
+}
+
+/// Spending limits in USD, over all repositories. Days and months are in UTC.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Budget {
+    pub daily: Option<f64>,
+    pub monthly: Option<f64>,
+    pub action: BudgetAction,
+}
+
+impl Budget {
+    /// Warns, or fails if the action is [`BudgetAction::Block`], when a budget is used up.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if self.daily.is_none() && self.monthly.is_none() {
+            return Ok(());
+        }
+        let Some(message) = self.exceeded(&read()?, now()) else {
+            return Ok(());
+        };
+        match self.action {
+            BudgetAction::Warn => {
+                eprintln!("{} {message}", "!".yellow());
+                Ok(())
+            }
+            BudgetAction::Block => Err(Failure::OverBudget(message).into()),
+        }
+    }
+
+    /// Describes the first budget that `entries` used up by `now`.
+    fn exceeded(&self, entries: &[Entry], now: DateTime<Utc>) -> Option<String> {
+        let (today, month) = spent(entries, now);
+        if let Some(limit) = self.daily.filter(|limit| today >= *limit) {
+            return Some(format!(
+                "Today's usage of ~${today:.4} reached the daily budget of ${limit}."
+            ));
+        }
+        if let Some(limit) = self.monthly.filter(|limit| month >= *limit) {
+            return Some(format!(
+                "This month's usage of ~${month:.4} reached the monthly budget of ${limit}."
+            ));
+        }
+        None
+    }
+}
+
+/// The cost of `entries` on the day and in the month of `now`.
+fn spent(entries: &[Entry], now: DateTime<Utc>) -> (f64, f64) {
+    let same_month = |entry: &&Entry| {
+        entry.timestamp.year() == now.year() && entry.timestamp.month() == now.month()
+    };
+    let today = entries
+        .iter()
+        .filter(same_month)
+        .filter(|entry| entry.timestamp.day() == now.day())
+        .map(|entry| entry.cost)
+        .sum();
+    let month = entries
+        .iter()
+        .filter(same_month)
+        .map(|entry| entry.cost)
+        .sum();
+    (today, month)
+}
+
+/// How `turbocommit usage` groups the requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    #[default]
+    Day,
+    Month,
+    Repo,
+    Model,
+}
+
+/// The totals of a group of requests.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub requests: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub cost: f64,
+}
+
+impl Summary {
+    fn add(&mut self, entry: &Entry) {
+        self.requests += 1;
+        self.prompt_tokens += entry.prompt_tokens;
+        self.completion_tokens += entry.completion_tokens;
+        self.cost += entry.cost;
+    }
+}
+
+/// Sums up `entries` per day, month, repository or model.
+pub fn summarize(entries: &[Entry], by: GroupBy) -> BTreeMap<String, Summary> {
+    let mut groups = BTreeMap::<String, Summary>::new();
+    for entry in entries {
+        let key = match by {
+            GroupBy::Day => entry.timestamp.format("%Y-%m-%d").to_string(),
+            GroupBy::Month => entry.timestamp.format("%Y-%m").to_string(),
+            GroupBy::Repo => entry
+                .repo
+                .clone()
+                .unwrap_or_else(|| String::from("(no repository)")),
+            GroupBy::Model => entry.model.clone(),
+        };
+        groups.entry(key).or_default().add(entry);
+    }
+    groups
+}
+
+/// Prints the requests since `since` grouped `by`, and how much of the budgets is used.
+pub fn print_summary(by: GroupBy, since: Option<NaiveDate>, budget: &Budget) -> anyhow::Result<()> {
+    let entries = read()?;
+    let selected = entries
+        .iter()
+        .filter(|entry| since.is_none_or(|since| entry.timestamp.date_naive() >= since))
+        .cloned()
+        .collect::<Vec<_>>();
+    if selected.is_empty() {
+        let message = match since {
+            Some(since) if !entries.is_empty() => format!("No requests recorded since {since}."),
+            _ => String::from("No requests recorded yet."),
+        };
+        println!("{}", message.bright_black());
+        return Ok(());
+    }
+
+    let groups = summarize(&selected, by);
+    let width = groups.keys().map(String::len).max().unwrap_or(0).max(5);
+    println!(
+        "{}",
+        format!(
+            "{:<width$}  {:>8}  {:>10}  {:>10}  {:>10}",
+            format!("{by:?}"),
+            "Requests",
+            "Prompt",
+            "Completion",
+            "Cost"
+        )
+        .bright_black()
+    );
+    let mut total = Summary::default();
+    for (key, summary) in &groups {
+        print_row(key, summary, width);
+        total.requests += summary.requests;
+        total.prompt_tokens += summary.prompt_tokens;
+        total.completion_tokens += summary.completion_tokens;
+        total.cost += summary.cost;
+    }
+    if groups.len() > 1 {
+        print_row("Total", &total, width);
+    }
+
+    let (today, month) = spent(&entries, now());
+    for (period, spent, limit) in [
+        ("Today", today, budget.daily),
+        ("This month", month, budget.monthly),
+    ] {
+        let limit = limit.map_or_else(String::new, |limit| format!(" of ${limit}"));
+        println!(
+            "{}",
+            format!("{period}: {}{limit}", format!("~${spent:.4}").purple()).bright_black()
+        );
+    }
+    Ok(())
+}
+
+fn print_row(key: &str, summary: &Summary, width: usize) {
+    println!(
+        "{key:<width$}  {:>8}  {:>10}  {:>10}  {}",
+        summary.requests,
+        summary.prompt_tokens,
+        summary.completion_tokens,
+        format!("{:>10}", format!("~${:.4}", summary.cost)).purple()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: &str, repo: &str, cost: f64) -> Entry {
+        Entry {
+            timestamp: timestamp.parse().unwrap(),
+            model: String::from("gpt-4o"),
+            prompt_tokens: 100,
+            completion_tokens: 10,
+            cost,
+            repo: Some(repo.to_string()),
+            estimated: false,
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let entries = [
+            entry("2026-10-17T23:59:00Z", "/a", 0.5),
+            entry("2026-10-18T08:00:00Z", "/a", 0.25),
+            entry("2026-10-18T09:00:00Z", "/b", 0.25),
+        ];
+        let by_day = summarize(&entries, GroupBy::Day);
+        assert_eq!(by_day.len(), 2);
+        assert_eq!(
+            by_day["2026-10-18"],
+            Summary {
+                requests: 2,
+                prompt_tokens: 200,
+                completion_tokens: 20,
+                cost: 0.5
+            }
+        );
+        assert_eq!(summarize(&entries, GroupBy::Repo)["/a"].requests, 2);
+        assert_eq!(summarize(&entries, GroupBy::Month)["2026-10"].cost, 1.0);
+
+        let line = serde_json::to_string(&entries[0]).unwrap();
+        assert_eq!(serde_json::from_str::<Entry>(&line).unwrap(), entries[0]);
+    }
+
+    #[test]
+    fn test_budget() {
+        let entries = [
+            entry("2026-09-30T12:00:00Z", "/a", 5.0),
+            entry("2026-10-17T12:00:00Z", "/a", 2.0),
+            entry("2026-10-18T08:00:00Z", "/a", 0.5),
+        ];
+        let now = "2026-10-18T12:00:00Z".parse().unwrap();
+        assert_eq!(spent(&entries, now), (0.5, 2.5));
+
+        let budget = |daily, monthly| Budget {
+            daily,
+            monthly,
+            action: BudgetAction::Block,
+        };
+        assert_eq!(budget(Some(1.0), Some(3.0)).exceeded(&entries, now), None);
+        assert!(budget(Some(0.5), None)
+            .exceeded(&entries, now)
+            .is_some_and(|message| message.contains("daily budget")));
+        assert!(budget(None, Some(2.0))
+            .exceeded(&entries, now)
+            .is_some_and(|message| message.contains("monthly budget")));
+    }
+}
@@ -1,70 +1,510 @@
 use actor::Actor;
+use cli::{
+    Cli, Command, CommitArgs, ConfigAction, GenerationArgs, HookAction, Options, OutputFormat,
+    PromptAction,
+};
 use colored::Colorize;
 use config::Config;
+use error::Failure;
 
+use model::Model;
 use openai::Message;
 
-use std::{env, process, time::Duration};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::{env, fs, process::ExitCode};
 
 mod actor;
 mod animation;
+mod clean;
 mod cli;
 mod config;
+mod editor;
+mod error;
 mod git;
+mod hook;
+mod interrupt;
+mod ledger;
+mod migrate;
 mod model;
 mod openai;
+mod retry;
+mod structured;
+mod template;
+mod ticket;
+mod trailers;
 mod util;
+mod validate;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> ExitCode {
     let config = Config::load();
-    match config.save_if_changed() {
-        Ok(_) => (),
+    let cli = Cli::parse_with_help(&config);
+    let json = cli
+        .generation()
+        .is_some_and(|args| args.output == OutputFormat::Json);
+
+    match run(config, cli).await.map_err(interrupt::from_prompt) {
+        Ok(()) => ExitCode::SUCCESS,
         Err(err) => {
-            println!("{}", format!("Unable to write to config: {err}").red());
-            process::exit(1);
+            let code = err.downcast_ref::<Failure>().map_or(1, Failure::exit_code);
+            if json {
+                let doc = serde_json::json!({ "error": err.to_string(), "exit_code": code });
+                println!("{doc}");
+            } else {
+                println!("{}", format!("{err}").red());
+            }
+            ExitCode::from(code)
         }
     }
-    let options = cli::Options::new(env::args(), &config);
+}
 
-    let Ok(api_key) = env::var("OPENAI_API_KEY") else {
-        println!("{} {}", "OPENAI_API_KEY not set.".red(), "Refer to step 3 here: https://help.openai.com/en/articles/5112595-best-practices-for-api-key-safety".bright_black());
-        process::exit(1);
+async fn run(config: Config, cli: Cli) -> anyhow::Result<()> {
+    let profile = match &cli.command {
+        Some(Command::Prompt {
+            action: PromptAction::Render { profile, .. },
+        }) => profile.clone(),
+        _ => cli.generation().and_then(|args| args.profile.clone()),
+    };
+    let command = cli.command.unwrap_or(Command::Commit(cli.commit));
+    let config = match command {
+        Command::Commit(_)
+        | Command::Pr(_)
+        | Command::Changelog(_)
+        | Command::Prompt { .. }
+        | Command::Hook {
+            action: HookAction::Run { .. },
+        } => config.with_profile(profile.as_deref())?,
+        _ => config,
     };
 
-    let mut actor = Actor::new(options.clone(), api_key);
+    match command {
+        Command::Commit(args) => commit(&config, &args).await,
+        Command::Pr(args) => {
+            let repo = git::get_repo()?;
+            let Some(base) = args.base.or_else(|| git::default_branch(&repo)) else {
+                anyhow::bail!("Could not find a main or master branch. Please pass --base.");
+            };
+            let diff = git::branch_diff(&repo, &base)?;
+            if diff.is_empty() {
+                anyhow::bail!("There are no changes compared to {base}.");
+            }
+            print_generated(&config, &args.generation, config::PR_SYSTEM_MSG, diff).await
+        }
+        Command::Changelog(args) => {
+            let repo = git::get_repo()?;
+            let range = args
+                .range
+                .or_else(|| git::latest_tag(&repo).map(|tag| format!("{tag}..HEAD")));
+            let commits = git::log(&repo, range.as_deref())?;
+            if commits.is_empty() {
+                anyhow::bail!("There are no commits in the range.");
+            }
+            print_generated(
+                &config,
+                &args.generation,
+                config::CHANGELOG_SYSTEM_MSG,
+                commits.join("\n\n---\n\n"),
+            )
+            .await
+        }
+        Command::Config { action } => {
+            match action {
+                ConfigAction::Path => println!("{}", Config::path()?.display()),
+                ConfigAction::Show { origin, profile } => {
+                    let config = match profile {
+                        Some(name) => config.with_profile(Some(&name))?,
+                        None => config,
+                    };
+                    if origin {
+                        config.print_origins();
+                    } else {
+                        print!("{}", serde_yaml::to_string(&config)?);
+                    }
+                }
+                ConfigAction::Init { repo } => {
+                    let path = config_path(repo)?;
+                    Config::init(&path)?;
+                    println!(
+                        "{} {}",
+                        "Config created!".purple(),
+                        path.display().to_string().bright_black()
+                    );
+                }
+                ConfigAction::Migrate { repo } => migrate::run(&config_path(repo)?)?,
+                ConfigAction::Set { key, value, repo } => {
+                    Config::set(&config_path(repo)?, &key, &value)?;
+                }
+            }
+            Ok(())
+        }
+        Command::Prompt {
+            action: PromptAction::Render { instruction, .. },
+        } => {
+            let repo = git::get_repo()?;
+            let files = git::without_ignored(git::files(&git::staged(&repo)?), &config.ignore)?;
+            let mut options = Options::from(&config);
+            let (prompt, _) = system_prompt(&config, &mut options, &files, &instruction)?;
+            println!("{prompt}");
+            Ok(())
+        }
+        Command::Usage { by, since } => {
+            ledger::print_summary(by, since, &Options::from(&config).budget)
+        }
+        Command::Completions { shell } => {
+            cli::print_completions(shell, &config);
+            Ok(())
+        }
+        Command::Man { out_dir } => Ok(cli::print_man(out_dir.as_deref(), &config)?),
+        Command::Hook { action } => match action {
+            HookAction::Install => hook::install(&git::get_repo()?),
+            HookAction::Uninstall => hook::uninstall(&git::get_repo()?),
+            HookAction::Run { file, source, .. } => {
+                // only plain `git commit`, not -m, templates, merges, squashes or amends
+                if source.is_some() {
+                    return Ok(());
+                }
+                let mut options = Options::new(&GenerationArgs::default(), &config);
+                options.print_once = true;
+                options.structured = config.structured_output;
+                if options.structured {
+                    structured::ensure_supported(options.model)?;
+                }
+                options.commit_message = true;
+                let mut actor = new_actor(&options)?;
+                let repo = git::get_repo()?;
+                let staged = git::staged(&repo)?;
+                let files = git::without_ignored(git::files(&staged), &config.ignore)?;
+                let (system_msg, ticket) = system_prompt(&config, &mut options, &files, &[])?;
+                let (diff, diff_tokens, model) = util::decide_diff(
+                    &staged,
+                    &config.ignore,
+                    openai::count_token(&system_msg)?,
+                    &options.models(),
+                    false,
+                )?;
+                options.model = model;
+                actor.use_model(model);
+                if let Some(ticket) = ticket {
+                    actor.reference_ticket(ticket, config.ticket_placement);
+                }
+                actor.add_trailers(commit_trailers(&config, &repo, None)?);
+                prepare(&mut actor, &options, &system_msg, diff, diff_tokens)?;
+                if let Some(message) = actor.generate().await? {
+                    hook::write_message(&file, &message)?;
+                }
+                Ok(())
+            }
+        },
+    }
+}
+
+async fn commit(config: &Config, args: &CommitArgs) -> anyhow::Result<()> {
+    let mut options = Options::new(&args.generation, config);
+    options.structured = args.structured || config.structured_output;
+    if options.structured {
+        structured::ensure_supported(options.model)?;
+    }
+    options.commit_message = true;
+    options.prints_result = args.diff_file.is_some();
+    let mut actor = new_actor(&options)?;
 
-    let repo = git::get_repo()?;
+    let repo;
+    let source = match &args.diff_file {
+        Some(path) => {
+            let patch = if path.as_os_str() == "-" {
+                let mut patch = Vec::new();
+                io::stdin().read_to_end(&mut patch)?;
+                patch
+            } else {
+                fs::read(path)?
+            };
+            git::parse_patch(&patch).map_err(|err| {
+                anyhow::anyhow!(
+                    "Unable to parse {}: {}\nOnly git-style patches are supported, `git diff --no-index` can create them.",
+                    path.display(),
+                    err.message()
+                )
+            })?
+        }
+        None => {
+            repo = git::get_repo()?;
+            if args.all || args.include_untracked {
+                let source = git::working_tree(&repo, args.include_untracked)?;
+                actor.stage_on_commit(git::files(&source));
+                source
+            } else {
+                let staged = git::staged(&repo)?;
+                if staged.deltas().len() == 0 && !options.non_interactive {
+                    let selected = util::select_files_to_stage(&repo)?;
+                    if options.dry_run {
+                        // what would be staged, without touching the index
+                        git::selection(&repo, &selected)?
+                    } else {
+                        git::stage(&repo, &selected)?;
+                        git::staged(&repo)?
+                    }
+                } else {
+                    staged
+                }
+            }
+        }
+    };
 
-    let system_len = openai::count_token(&config.system_msg).unwrap_or(0);
+    let files = git::without_ignored(git::files(&source), &config.ignore)?;
+    let (system_msg, ticket) =
+        system_prompt(config, &mut options, &files, &args.generation.instruction)?;
+    if let Some(ticket) = ticket {
+        actor.reference_ticket(ticket, config.ticket_placement);
+    }
+    // a patch file may come from outside of a repository, and then has no identity to sign off
+    if let Ok(repo) = git::get_repo() {
+        actor.add_trailers(commit_trailers(config, &repo, Some(args))?);
+    }
+    if args.diff_file.is_none() {
+        actor.sign(args.signing());
+        actor.describe_change(editor::Context {
+            instruction: args.generation.instruction.join(" "),
+            stat: git::stat(&source, &files)?,
+        });
+    }
+    let system_len = openai::count_token(&system_msg).unwrap_or(0);
     let extra_len = openai::count_token(&options.msg).unwrap_or(0);
 
-    let (diff, diff_tokens) =
-        util::decide_diff(&repo, system_len + extra_len, options.model.context_size())?;
+    let (diff, diff_tokens, model) = util::decide_diff(
+        &source,
+        &config.ignore,
+        system_len + extra_len,
+        &options.models(),
+        options.non_interactive,
+    )?;
+    options.model = model;
+    actor.use_model(model);
 
-    actor.add_message(Message::system(config.system_msg.clone()));
-    actor.add_message(Message::user(diff));
+    prepare(&mut actor, &options, &system_msg, diff, diff_tokens)?;
 
-    if !options.msg.is_empty() {
-        actor.add_message(Message::user(options.msg));
+    if options.dry_run {
+        return actor.dry_run();
+    }
+    if options.output == OutputFormat::Json {
+        return actor.print_json().await;
+    }
+    if !confirm_cost(&mut actor, &options)? {
+        return Ok(());
+    }
+    if args.diff_file.is_some() {
+        if let Some(message) = actor.generate().await? {
+            println!("{message}");
+        }
+        return Ok(());
     }
-
-    actor.used_tokens = system_len + extra_len + diff_tokens;
 
     let result = actor.start().await;
 
+    if options.non_interactive {
+        return result;
+    }
+
     util::check_version().await;
 
-    if util::check_config_age(Duration::from_secs(60 * 60 * 24 * 30 * 6)) {
-        if !util::is_system_prompt_same_as_default(&config.system_msg) {
-            println!(
-                "\n{}\n{}\n{}",
-                "Your system prompt seems to be old.".yellow(),
-                "There is a new default recommended system prompt. To apply it, delete the `system_msg` field in your config file.".bright_black(),
-                "To get rid of this message, simply save your config file to change the last modified date.".bright_black()
+    for path in &config.outdated {
+        println!(
+            "\n{}\n{}",
+            format!(
+                "{} is from an older version of turbocommit.",
+                path.display()
+            )
+            .yellow(),
+            "Run `turbocommit config migrate` to upgrade it, with `--repo` for a repo config."
+                .bright_black()
+        );
+    }
+
+    result
+}
+
+/// Generates text from `content` and prints the chosen result instead of committing.
+async fn print_generated(
+    config: &Config,
+    args: &GenerationArgs,
+    system_msg: &str,
+    content: String,
+) -> anyhow::Result<()> {
+    let mut options = Options::new(args, config);
+    options.prints_result = true;
+    let mut actor = new_actor(&options)?;
+    let content_tokens = openai::count_token(&content)?;
+    prepare(&mut actor, &options, system_msg, content, content_tokens)?;
+    let Some(model) = util::fitting_model(&options.models(), actor.used_tokens) else {
+        let models = options.models();
+        return Err(Failure::TooLarge {
+            tokens: actor.used_tokens,
+            max: models.iter().map(Model::context_size).max().unwrap_or(0),
+        }
+        .into());
+    };
+    util::report_fallback(options.model, model);
+    options.model = model;
+    actor.use_model(model);
+
+    if options.dry_run {
+        return actor.dry_run();
+    }
+    if options.output == OutputFormat::Json {
+        return actor.print_json().await;
+    }
+    if !confirm_cost(&mut actor, &options)? {
+        return Ok(());
+    }
+    if let Some(text) = actor.generate().await? {
+        println!("{text}");
+    }
+    Ok(())
+}
+
+/// Renders the system prompt template for a change to `files`, and returns it with the
+/// ticket key of the branch. The instruction becomes part of the prompt instead of a
+/// message of its own if the template has a place for it.
+fn system_prompt(
+    config: &Config,
+    options: &mut Options,
+    files: &[String],
+    instruction: &[String],
+) -> anyhow::Result<(String, Option<String>)> {
+    let template = config.system_prompt();
+    if template::uses(&template, "user_instruction") {
+        options.msg.clear();
+    }
+    let repo = git::get_repo().ok();
+    let ticket = match repo.as_ref().and_then(git::current_branch) {
+        Some(branch) => ticket::extract(&config.ticket_pattern, &branch)?,
+        None => None,
+    };
+    let vars = template::vars(
+        repo.as_ref(),
+        ticket.as_deref(),
+        files,
+        &instruction.join(" "),
+    );
+    let mut prompt = template::render(&template, &vars)?;
+    if let Some(commit_template) = repo.as_ref().and_then(editor::template) {
+        prompt.push_str(&format!(
+            "\n\nFollow the structure of the commit message template of this repository, its comment lines explain it:\n{}",
+            commit_template.trim_end()
+        ));
+    }
+    if let Some(instruction) = ticket
+        .as_deref()
+        .and_then(|ticket| ticket::instruction(ticket, config.ticket_placement))
+    {
+        prompt.push_str("\n\n");
+        prompt.push_str(&instruction);
+    }
+    Ok((prompt, ticket))
+}
+
+/// The configured trailers, then co-authors, then the sign-off, like git orders them.
+fn commit_trailers(
+    config: &Config,
+    repo: &git2::Repository,
+    args: Option<&CommitArgs>,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let mut result = trailers::parse_all(&config.trailers)?;
+    let Some(args) = args else {
+        if config.signoff {
+            result.push((String::from("Signed-off-by"), git::identity(repo)?));
+        }
+        return Ok(result);
+    };
+    result.extend(trailers::parse_all(&args.trailer)?);
+
+    let mut co_authors = Vec::new();
+    if !args.co_author.is_empty() || args.pick_co_authors {
+        let me = git::identity(repo).unwrap_or_default();
+        let authors = git::recent_authors(repo, 200)
+            .into_iter()
+            .filter(|author| *author != me)
+            .collect::<Vec<_>>();
+        for query in &args.co_author {
+            co_authors.push(trailers::co_author(query, &authors)?);
+        }
+        if args.pick_co_authors {
+            if args.generation.non_interactive || args.generation.output == OutputFormat::Json {
+                anyhow::bail!("--pick-co-authors needs an interactive terminal, use --co-author.");
+            }
+            if authors.is_empty() {
+                anyhow::bail!("There are no other recent authors to pick from.");
+            }
+            co_authors.extend(
+                inquire::MultiSelect::new("Co-authors:", authors)
+                    .with_vim_mode(true)
+                    .prompt()?,
             );
         }
     }
+    result.extend(
+        co_authors
+            .into_iter()
+            .map(|author| (String::from("Co-authored-by"), author)),
+    );
 
-    result
+    if config.signoff || args.signoff {
+        result.push((String::from("Signed-off-by"), git::identity(repo)?));
+    }
+    Ok(result)
+}
+
+fn config_path(repo: bool) -> anyhow::Result<PathBuf> {
+    if !repo {
+        return Config::path();
+    }
+    Config::repo_path()
+        .ok_or_else(|| anyhow::anyhow!("Not inside a git repository with a working tree."))
+}
+
+fn new_actor(options: &Options) -> anyhow::Result<Actor> {
+    let api_key = env::var(&options.api_key_env).ok();
+    if api_key.is_none() && !options.dry_run {
+        anyhow::bail!(
+            "{} {}",
+            format!("{} not set.", options.api_key_env),
+            "Refer to step 3 here: https://help.openai.com/en/articles/5112595-best-practices-for-api-key-safety".bright_black()
+        );
+    }
+    Ok(Actor::new(options.clone(), api_key.unwrap_or_default()))
+}
+
+/// Lets the user confirm an expensive request, seeing its estimated cost, or switch to
+/// a cheaper model. Returns whether to send the request. Never asks with `--yes`.
+fn confirm_cost(actor: &mut Actor, options: &Options) -> anyhow::Result<bool> {
+    let threshold = options
+        .confirm_cost_above
+        .filter(|_| !options.non_interactive);
+    let n = options.n.max(1) as usize;
+    match util::confirm_cost(options.model, actor.used_tokens, n, threshold)? {
+        Some(model) => {
+            actor.use_model(model);
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+fn prepare(
+    actor: &mut Actor,
+    options: &Options,
+    system_msg: &str,
+    content: String,
+    content_tokens: usize,
+) -> anyhow::Result<()> {
+    actor.add_message(Message::system(system_msg.to_string()));
+    actor.add_message(Message::user(content));
+
+    if !options.msg.is_empty() {
+        actor.add_message(Message::user(options.msg.clone()));
+    }
+
+    actor.used_tokens =
+        openai::count_token(system_msg)? + openai::count_token(&options.msg)? + content_tokens;
+    Ok(())
 }
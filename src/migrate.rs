@@ -0,0 +1,239 @@
+use std::path::Path;
+
+use colored::Colorize;
+use serde_yaml::{Mapping, Value};
+use similar::{ChangeTag, TextDiff};
+
+use crate::config::{self, Config};
+
+/// The config format version this build writes.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// What changed in the config format with a version.
+struct Migration {
+    version: u32,
+    /// Keys renamed in this version, as `(old, new)`.
+    renames: &'static [(&'static str, &'static str)],
+    /// Whether the default system message changed in this version.
+    new_system_msg: bool,
+}
+
+/// Every version bump, oldest first. Configs without a `version` are version 0.
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    renames: &[],
+    new_system_msg: true,
+}];
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Rename {
+        from: String,
+        to: String,
+    },
+    /// The config has its own system message, while there is a newer default.
+    SystemMsg {
+        current: String,
+    },
+}
+
+/// The changes that migrating `layer` to the current version would make.
+pub fn pending(layer: &Mapping) -> Vec<Change> {
+    pending_with(layer, MIGRATIONS, &Config::default().system_msg)
+}
+
+fn pending_with(
+    layer: &Mapping,
+    migrations: &[Migration],
+    default_system_msg: &str,
+) -> Vec<Change> {
+    let version = version(layer);
+    let mut keys = layer
+        .keys()
+        .filter_map(Value::as_str)
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let mut changes = Vec::new();
+    let mut new_system_msg = false;
+
+    for migration in migrations.iter().filter(|m| m.version > version) {
+        for (from, to) in migration.renames {
+            if let Some(key) = keys.iter_mut().find(|key| key == from) {
+                *key = to.to_string();
+                changes.push(Change::Rename {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                });
+            }
+        }
+        new_system_msg |= migration.new_system_msg;
+    }
+
+    if new_system_msg {
+        let current = layer
+            .get("system_msg")
+            .and_then(Value::as_str)
+            .filter(|msg| !msg.trim().is_empty() && *msg != default_system_msg);
+        if let Some(current) = current {
+            changes.push(Change::SystemMsg {
+                current: current.to_string(),
+            });
+        }
+    }
+    changes
+}
+
+/// Applies the key renames of newer versions in place, so older configs keep working
+/// until they are migrated. A renamed key never overrides a value set under the new name.
+pub fn rename_keys(layer: &mut Mapping) {
+    rename_keys_with(layer, MIGRATIONS);
+}
+
+fn rename_keys_with(layer: &mut Mapping, migrations: &[Migration]) {
+    let version = version(layer);
+    for migration in migrations.iter().filter(|m| m.version > version) {
+        for (from, to) in migration.renames {
+            if let Some(value) = layer.remove(*from) {
+                if !layer.contains_key(*to) {
+                    layer.insert(Value::from(*to), value);
+                }
+            }
+        }
+    }
+}
+
+fn version(layer: &Mapping) -> u32 {
+    layer
+        .get("version")
+        .and_then(Value::as_u64)
+        .and_then(|version| u32::try_from(version).ok())
+        .unwrap_or(0)
+}
+
+/// Migrates the config file at `path` to the current version, asking before replacing
+/// a custom system message with the new default.
+pub fn run(path: &Path) -> anyhow::Result<()> {
+    let mut content = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("Unable to read {}: {err}", path.display()))?;
+    let layer = serde_yaml::from_str::<Option<Mapping>>(&content)?.unwrap_or_default();
+    if version(&layer) > CURRENT_VERSION {
+        anyhow::bail!(
+            "{} is from a newer version of turbocommit. Please update turbocommit.",
+            path.display()
+        );
+    }
+
+    for change in pending(&layer) {
+        match change {
+            Change::Rename { from, to } => {
+                content = config::rename_key(&content, &from, &to);
+                println!("{} {from} -> {to}", "Renamed".purple());
+            }
+            Change::SystemMsg { current } => {
+                println!(
+                    "{}",
+                    "There is a new default system message. Your config has its own:".purple()
+                );
+                print_diff(&current, &Config::default().system_msg);
+                let accept = inquire::Confirm::new(
+                    "Remove your system message to use the new default, and future ones?",
+                )
+                .with_default(true)
+                .prompt()?;
+                if accept {
+                    content = config::remove_key(&content, "system_msg");
+                }
+            }
+        }
+    }
+
+    content = config::set_key(&content, "version", &Value::from(CURRENT_VERSION))?;
+    std::fs::write(path, content)?;
+    println!(
+        "{} {}",
+        format!("Config migrated to version {CURRENT_VERSION}.").purple(),
+        path.display().to_string().bright_black()
+    );
+    Ok(())
+}
+
+fn print_diff(old: &str, new: &str) {
+    for change in TextDiff::from_lines(old, new).iter_all_changes() {
+        let line = change.to_string_lossy();
+        let line = line.trim_end_matches('\n');
+        match change.tag() {
+            ChangeTag::Delete => println!("{}", format!("- {line}").red()),
+            ChangeTag::Insert => println!("{}", format!("+ {line}").green()),
+            ChangeTag::Equal => println!("{}", format!("  {line}").bright_black()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            renames: &[("n", "default_number_of_choices")],
+            new_system_msg: false,
+        },
+        Migration {
+            version: 2,
+            renames: &[("temp", "default_temperature")],
+            new_system_msg: true,
+        },
+    ];
+
+    fn layer(yaml: &str) -> Mapping {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_pending_changes_depend_on_version() {
+        let old = layer("n: 3\ntemp: 0.5\nsystem_msg: my prompt");
+        assert_eq!(
+            pending_with(&old, MIGRATIONS, "default prompt"),
+            vec![
+                Change::Rename {
+                    from: String::from("n"),
+                    to: String::from("default_number_of_choices"),
+                },
+                Change::Rename {
+                    from: String::from("temp"),
+                    to: String::from("default_temperature"),
+                },
+                Change::SystemMsg {
+                    current: String::from("my prompt"),
+                },
+            ]
+        );
+
+        let newer = layer("version: 1\nn: 3\nsystem_msg: default prompt");
+        assert!(pending_with(&newer, MIGRATIONS, "default prompt").is_empty());
+
+        let current = layer("version: 2\ntemp: 0.5\nsystem_msg: my prompt");
+        assert!(pending_with(&current, MIGRATIONS, "default prompt").is_empty());
+    }
+
+    #[test]
+    fn test_rename_keys_keeps_new_names() {
+        let mut old = layer("n: 3\ntemp: 0.5\ndefault_temperature: 1.0");
+        rename_keys_with(&mut old, MIGRATIONS);
+        assert_eq!(
+            old,
+            layer("default_number_of_choices: 3\ndefault_temperature: 1.0")
+        );
+    }
+
+    #[test]
+    fn test_current_config_needs_no_migration() {
+        let defaults = serde_yaml::to_value(Config::default()).unwrap();
+        assert!(pending(defaults.as_mapping().unwrap()).is_empty());
+        assert_eq!(
+            super::MIGRATIONS.last().map(|m| m.version),
+            Some(CURRENT_VERSION)
+        );
+    }
+}
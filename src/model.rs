@@ -1,3 +1,4 @@
+use std::fmt;
 use std::str::FromStr;
 use serde::{Serialize, Deserialize};
 
@@ -53,28 +54,29 @@ impl FromStr for Model {
 }
 
 
-impl ToString for Model {
-    fn to_string(&self) -> String {
-        match self {
-            Self::Gpt40125Preview { .. } => String::from("gpt-4-0125-preview"),
-            Self::Gpt4TurboPreview { .. } => String::from("gpt-4-turbo-preview"),
-            Self::Gpt41106Preview { .. } => String::from("gpt-4-1106-preview"),
-            Self::Gpt4VisionPreview { .. } => String::from("gpt-4-vision-preview"),
-            Self::Gpt4 { .. } => String::from("gpt-4"),
-            Self::Gpt40613 { .. } => String::from("gpt-4-0613"),
-            Self::Gpt432k { .. } => String::from("gpt-4-32k"),
-            Self::Gpt432k0613 { .. } => String::from("gpt-4-32k-0613"),
-            Self::Gpt35Turbo { .. } => String::from("gpt-3.5-turbo"),
-            Self::Gpt35Turbo16k { .. } => String::from("gpt-3.5-turbo-16k"),
-            Self::Gpt35Turbo0613 { .. } => String::from("gpt-3.5-turbo-0613"),
-            Self::Gpt35Turbo1106 { .. } => String::from("gpt-3.5-turbo-1106"),
-            Self::Gpt35Turbo0125 { .. } => String::from("gpt-3.5-turbo-0125"),
-            Self::Gpt35Turbo16k0613 { .. } => String::from("gpt-3.5-turbo-16k-0613"),
-            Self::Gpt4o { .. } => String::from("gpt-4o"),
-            Self::Gpt4o20240513 { .. } => String::from("gpt-4o-2024-05-13"),
-            Self::Gpt4Turbo20240409 { .. } => String::from("gpt-4-turbo-2024-04-09"),
-            Self::Gpt35TurboInstruct { .. } => String::from("gpt-3.5-turbo-instruct"),
-        }
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Gpt40125Preview { .. } => "gpt-4-0125-preview",
+            Self::Gpt4TurboPreview { .. } => "gpt-4-turbo-preview",
+            Self::Gpt41106Preview { .. } => "gpt-4-1106-preview",
+            Self::Gpt4VisionPreview { .. } => "gpt-4-vision-preview",
+            Self::Gpt4 { .. } => "gpt-4",
+            Self::Gpt40613 { .. } => "gpt-4-0613",
+            Self::Gpt432k { .. } => "gpt-4-32k",
+            Self::Gpt432k0613 { .. } => "gpt-4-32k-0613",
+            Self::Gpt35Turbo { .. } => "gpt-3.5-turbo",
+            Self::Gpt35Turbo16k { .. } => "gpt-3.5-turbo-16k",
+            Self::Gpt35Turbo0613 { .. } => "gpt-3.5-turbo-0613",
+            Self::Gpt35Turbo1106 { .. } => "gpt-3.5-turbo-1106",
+            Self::Gpt35Turbo0125 { .. } => "gpt-3.5-turbo-0125",
+            Self::Gpt35Turbo16k0613 { .. } => "gpt-3.5-turbo-16k-0613",
+            Self::Gpt4o { .. } => "gpt-4o",
+            Self::Gpt4o20240513 { .. } => "gpt-4o-2024-05-13",
+            Self::Gpt4Turbo20240409 { .. } => "gpt-4-turbo-2024-04-09",
+            Self::Gpt35TurboInstruct { .. } => "gpt-3.5-turbo-instruct",
+        };
+        f.write_str(name)
     }
 }
 
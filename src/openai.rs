@@ -1,18 +1,23 @@
 #![allow(dead_code)]
 
 use colored::Colorize;
-use crossterm::cursor::{MoveToColumn, MoveToPreviousLine};
-use crossterm::style::Print;
+use crossterm::cursor::{MoveToColumn, MoveToPreviousLine, Show};
+use crossterm::style::{Print, ResetColor};
 use crossterm::terminal::{Clear, ClearType};
 use crossterm::{execute, terminal};
 use futures::StreamExt;
 use reqwest_eventsource::{Event, EventSource};
 use serde::{Deserialize, Serialize};
-use std::{fmt, process};
+use std::fmt;
+use std::io::IsTerminal;
+use std::time::Duration;
+use tokio::time::Instant;
 
 use crate::animation;
+use crate::error::{ApiError, Failure};
 use crate::model::Model;
 use crate::util::count_lines;
+use crate::{interrupt, retry, structured};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 #[serde(rename_all = "lowercase")]
@@ -59,12 +64,35 @@ pub struct ErrorRoot {
 #[serde(rename_all = "camelCase")]
 pub struct Error {
     pub message: String,
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default)]
     pub type_field: String,
     pub param: Option<String>,
     pub code: Option<String>,
 }
 
+/// What went wrong with a request for `model`, from the HTTP `status` if there is one,
+/// and the error `body`, which is an [`ErrorRoot`] for OpenAI compatible APIs.
+pub fn api_error(status: Option<u16>, body: &str, model: &str) -> ApiError {
+    let error = serde_json::from_str::<ErrorRoot>(body)
+        .ok()
+        .map(|root| root.error);
+    let code = error
+        .as_ref()
+        .and_then(|error| error.code.as_deref())
+        .or_else(|| error.as_ref().map(|error| error.type_field.as_str()));
+    match (status, code) {
+        (_, Some("invalid_api_key")) | (Some(401), _) => ApiError::InvalidKey,
+        (_, Some("insufficient_quota")) => ApiError::QuotaExceeded,
+        (_, Some("context_length_exceeded")) => ApiError::ContextLengthExceeded,
+        (_, Some("model_not_found")) => ApiError::ModelNotFound(model.to_string()),
+        (_, Some("rate_limit_exceeded")) | (Some(429), _) => ApiError::RateLimited,
+        _ => ApiError::Other {
+            status,
+            message: error.map_or_else(|| body.trim().to_string(), |error| error.message),
+        },
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -85,6 +113,16 @@ pub struct Request {
     pub temperature: f64,
     pub frequency_penalty: f64,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StreamOptions {
+    /// Asks for a last chunk that reports the usage of the whole request.
+    include_usage: bool,
 }
 
 impl Request {
@@ -102,129 +140,178 @@ impl Request {
             temperature,
             frequency_penalty,
             stream: true,
+            stream_options: None,
+            response_format: None,
         }
     }
 
+    /// Asks for the message as JSON fields instead of free text, see [`structured`].
+    pub fn structured(mut self, format: structured::Format) -> Self {
+        self.response_format = Some(structured::response_format(format));
+        if format == structured::Format::JsonObject {
+            self.messages
+                .push(Message::system(structured::instruction()));
+        }
+        self
+    }
+
+    /// Asks for the usage of the request in a last chunk when `api_base` is the OpenAI API.
+    /// Other compatible servers may reject the field, so they are counted locally instead.
+    pub fn report_usage(mut self, api_base: &str) -> Self {
+        let is_openai =
+            reqwest::Url::parse(api_base).is_ok_and(|url| url.host_str() == Some("api.openai.com"));
+        if is_openai {
+            self.stream_options = Some(StreamOptions {
+                include_usage: true,
+            });
+        }
+        self
+    }
+
+    /// How a choice is shown and returned: rendered from its JSON in structured mode.
+    fn display(&self, content: &str) -> String {
+        if self.response_format.is_some() {
+            structured::render_response(content)
+        } else {
+            content.to_string()
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// Streams the response, and asks again as `retry` allows when an attempt fails with
+    /// a transient error. Each attempt starts over, so its tokens are never mixed into
+    /// the choices of an earlier one. Ctrl-C stops the request and returns the choices
+    /// streamed so far as a cancelled completion.
     pub async fn execute(
         &self,
+        api_base: &str,
         api_key: String,
-        no_animations: bool,
+        render: Render,
         model: Model,
         prompt_tokens: usize,
-    ) -> anyhow::Result<Vec<String>> {
-        let mut choices = vec![String::new(); self.n as usize];
-
-        let loading_ai_animation = animation::start(
-            String::from("Asking AI..."),
-            no_animations,
-            std::io::stdout(),
-        )
-        .await;
+        retry: retry::Policy,
+    ) -> anyhow::Result<Completion> {
+        let loading_ai_animation = match render {
+            // cursor movements would end up in a pipe or file
+            _ if !std::io::stdout().is_terminal() => None,
+            Render::Silent => None,
+            _ => Some(
+                animation::start(
+                    String::from("Asking AI..."),
+                    render == Render::Once,
+                    std::io::stdout(),
+                )
+                .await,
+            ),
+        };
 
-        let json = serde_json::to_string(self)?;
+        let json = self.to_json()?;
+        let client = reqwest::Client::new();
+        let url = format!("{}/chat/completions", api_base.trim_end_matches('/'));
 
-        let request_builder = reqwest::Client::new()
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Content-Type", "application/json")
-            .bearer_auth(api_key)
-            .body(json);
-
-        let term_width = terminal::size()?.0 as usize;
+        let deadline = Instant::now() + retry.timeout;
+        let mut streamed = Streamed::default();
+        let attempts = async {
+            let mut attempt = 1;
+            loop {
+                let request_builder = client
+                    .post(&url)
+                    .header("Content-Type", "application/json")
+                    .bearer_auth(&api_key)
+                    .body(json.clone());
+                let outcome = tokio::time::timeout_at(
+                    deadline,
+                    self.stream(
+                        &mut streamed,
+                        request_builder,
+                        render,
+                        model,
+                        prompt_tokens,
+                        loading_ai_animation.as_ref(),
+                    ),
+                )
+                .await;
+                let Ok(outcome) = outcome else {
+                    return Err(Failure::Api(ApiError::Timeout(retry.timeout)).into());
+                };
+                let Some((error, requested)) = outcome? else {
+                    return anyhow::Ok(());
+                };
+                let wait = retry::delay(attempt, requested);
+                if attempt >= retry.max_attempts
+                    || !retry::is_transient(&error)
+                    || Instant::now() + wait > deadline
+                {
+                    return Err(Failure::Api(error).into());
+                }
+                eprintln!(
+                    "{} {error} Trying again in {:.1}s ({}/{}).",
+                    "!".yellow(),
+                    wait.as_secs_f64(),
+                    attempt + 1,
+                    retry.max_attempts
+                );
+                tokio::time::sleep(wait).await;
+                attempt += 1;
+            }
+        };
+        let outcome = tokio::select! {
+            result = attempts => Some(result),
+            _ = interrupt::cancelled() => None,
+        };
 
         let mut stdout = std::io::stdout();
-
-        let mut es = EventSource::new(request_builder)?;
-        let mut lines_to_move_up = 0;
-        let mut response_tokens = 0;
-
-        while let Some(event) = es.next().await {
-            if no_animations {
-                match event {
-                    Ok(Event::Message(message)) => {
-                        if message.data == "[DONE]" {
-                            break;
-                        }
-                        let resp = serde_json::from_str::<Response>(&message.data)
-                            .map_or_else(|_| Response::default(), |r| r);
-                        response_tokens += 1;
-                        for choice in resp.choices {
-                            if let Some(content) = choice.delta.content {
-                                choices[choice.index as usize].push_str(&content);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        println!("{e}");
-                        process::exit(1);
-                    }
-                    _ => {}
-                }
-            } else {
-                if !loading_ai_animation.is_finished() {
-                    loading_ai_animation.abort();
+        if let Some(animation) = loading_ai_animation {
+            if !animation.is_finished() {
+                animation.abort();
+                execute!(stdout, Clear(ClearType::CurrentLine), MoveToColumn(0))?;
+            }
+        }
+        let cancelled = match outcome {
+            Some(result) => {
+                result?;
+                false
+            }
+            None => {
+                if render != Render::Silent {
+                    // Clears the `^C` the terminal echoed, and whatever color was cut off.
                     execute!(
-                        std::io::stdout(),
+                        stdout,
+                        ResetColor,
+                        Show,
                         Clear(ClearType::CurrentLine),
                         MoveToColumn(0),
                     )?;
-                    print!("\n\n")
                 }
-                match event {
-                    Ok(Event::Message(message)) => {
-                        if message.data == "[DONE]" {
-                            break;
-                        }
-                        execute!(stdout, MoveToPreviousLine(lines_to_move_up),)?;
-                        lines_to_move_up = 0;
-                        execute!(stdout, Clear(ClearType::FromCursorDown),)?;
-                        let resp = serde_json::from_str::<Response>(&message.data)
-                            .map_or_else(|_| Response::default(), |r| r);
-                        response_tokens += 1;
-                        for choice in resp.choices {
-                            if let Some(content) = choice.delta.content {
-                                choices[choice.index as usize].push_str(&content);
-                            }
-                        }
-                        for (i, choice) in choices.iter().enumerate() {
-                            let outp = format!(
-                                "{}{}\n{}\n",
-                                if i == 0 {
-                                    format!(
-                                        "This used {} tokens costing you about {}\n",
-                                        format!("{}", response_tokens + prompt_tokens).purple(),
-                                        format!(
-                                            "~${:0.4}",
-                                            model.cost(prompt_tokens, response_tokens)
-                                        )
-                                        .purple()
-                                    )
-                                    .bright_black()
-                                } else {
-                                    "".bright_black()
-                                },
-                                format!("[{}]====================", format!("{i}").purple())
-                                    .bright_black(),
-                                choice,
-                            );
-                            print!("{outp}");
-                            lines_to_move_up += count_lines(&outp, term_width) - 1;
-                        }
-                    }
-                    Err(e) => {
-                        println!("{e}");
-                        process::exit(1);
-                    }
-                    _ => {}
+                true
+            }
+        };
+        let Streamed { choices, usage, .. } = streamed;
+        let estimated = usage.is_none();
+        let usage = match usage {
+            Some(usage) => usage,
+            None => {
+                let bpe = tiktoken_rs::cl100k_base()?;
+                let completion_tokens = choices
+                    .iter()
+                    .map(|choice| bpe.encode_with_special_tokens(choice).len())
+                    .sum();
+                Usage {
+                    prompt_tokens,
+                    completion_tokens,
+                    total_tokens: prompt_tokens + completion_tokens,
                 }
             }
-        }
+        };
+        let choices = choices
+            .iter()
+            .map(|choice| self.display(choice))
+            .collect::<Vec<_>>();
 
-        if no_animations {
-            println!(
-                "This used {} tokens costing you about {}\n",
-                format!("{}", response_tokens + prompt_tokens).purple(),
-                format!("~${:0.4}", model.cost(prompt_tokens, response_tokens)).purple()
-            );
+        if render == Render::Once {
             for (i, choice) in choices.iter().enumerate() {
                 println!(
                     "[{}]====================\n{}\n",
@@ -234,13 +321,210 @@ impl Request {
             }
         }
 
-        execute!(
-            stdout,
-            Print(format!("{}\n", "=======================".bright_black())),
-        )?;
+        if render != Render::Silent {
+            execute!(
+                stdout,
+                Print(format!("{}\n", "=======================".bright_black())),
+            )?;
+            println!(
+                "{}",
+                format!(
+                    "{} used {} prompt and {} completion tokens{}, costing you about {}",
+                    model.to_string().purple(),
+                    format!("{}", usage.prompt_tokens).purple(),
+                    format!("{}", usage.completion_tokens).purple(),
+                    if estimated { " (estimated)" } else { "" },
+                    format!(
+                        "~${:0.4}",
+                        model.cost(usage.prompt_tokens, usage.completion_tokens)
+                    )
+                    .purple()
+                )
+                .bright_black()
+            );
+        }
 
-        Ok(choices)
+        Ok(Completion {
+            choices,
+            usage,
+            estimated,
+            cancelled,
+            model,
+        })
     }
+
+    /// One attempt at streaming the response into `streamed`, which starts over. Returns
+    /// why the attempt failed, and how long the server asked to wait before trying again.
+    /// If it fails, the choices drawn so far are cleared again.
+    async fn stream(
+        &self,
+        streamed: &mut Streamed,
+        request_builder: reqwest::RequestBuilder,
+        render: Render,
+        model: Model,
+        prompt_tokens: usize,
+        loading_ai_animation: Option<&tokio::task::JoinHandle<()>>,
+    ) -> anyhow::Result<Option<(ApiError, Option<Duration>)>> {
+        *streamed = Streamed {
+            choices: vec![String::new(); self.n as usize],
+            finished: vec![false; self.n as usize],
+            ..Streamed::default()
+        };
+
+        let term_width = match render {
+            Render::Stream => terminal::size()?.0 as usize,
+            _ => 0,
+        };
+
+        let mut stdout = std::io::stdout();
+
+        let mut es = EventSource::new(request_builder)?;
+        es.set_retry_policy(Box::new(reqwest_eventsource::retry::Never));
+        let mut lines_to_move_up = 0;
+
+        let failed = loop {
+            let Some(event) = es.next().await else {
+                break None;
+            };
+            if render == Render::Stream {
+                if let Some(animation) = loading_ai_animation {
+                    if !animation.is_finished() {
+                        animation.abort();
+                        execute!(
+                            std::io::stdout(),
+                            Clear(ClearType::CurrentLine),
+                            MoveToColumn(0),
+                        )?;
+                        print!("\n\n")
+                    }
+                }
+            }
+            match event {
+                Ok(Event::Message(message)) => {
+                    if message.data == "[DONE]" {
+                        break None;
+                    }
+                    if serde_json::from_str::<ErrorRoot>(&message.data).is_ok() {
+                        break Some((api_error(None, &message.data, &self.model), None));
+                    }
+                    let resp = serde_json::from_str::<Response>(&message.data).unwrap_or_default();
+                    if resp.usage.is_some() {
+                        streamed.usage = resp.usage;
+                    }
+                    for choice in resp.choices {
+                        if let Some(content) = choice.delta.content {
+                            streamed.choices[choice.index as usize].push_str(&content);
+                            streamed.response_tokens += 1;
+                        }
+                        if choice.finish_reason.is_some() {
+                            streamed.finished[choice.index as usize] = true;
+                        }
+                    }
+                    if render != Render::Stream {
+                        continue;
+                    }
+                    let response_tokens = streamed.response_tokens;
+                    execute!(stdout, MoveToPreviousLine(lines_to_move_up),)?;
+                    lines_to_move_up = 0;
+                    execute!(stdout, Clear(ClearType::FromCursorDown),)?;
+                    for (i, choice) in streamed.choices.iter().enumerate() {
+                        let outp = format!(
+                            "{}{}\n{}\n",
+                            if i == 0 {
+                                format!(
+                                    "So far about {} tokens, costing you about {}\n",
+                                    format!("{}", response_tokens + prompt_tokens).purple(),
+                                    format!("~${:0.4}", model.cost(prompt_tokens, response_tokens))
+                                        .purple()
+                                )
+                                .bright_black()
+                            } else {
+                                "".bright_black()
+                            },
+                            format!("[{}]====================", format!("{i}").purple())
+                                .bright_black(),
+                            self.display(choice),
+                        );
+                        print!("{outp}");
+                        lines_to_move_up += count_lines(&outp, term_width) - 1;
+                    }
+                }
+                Err(reqwest_eventsource::Error::InvalidStatusCode(status, response)) => {
+                    let requested = retry::requested_delay(response.headers());
+                    let body = response.text().await.unwrap_or_default();
+                    break Some((
+                        api_error(Some(status.as_u16()), &body, &self.model),
+                        requested,
+                    ));
+                }
+                Err(reqwest_eventsource::Error::StreamEnded)
+                    if streamed.finished.iter().all(|f| *f) =>
+                {
+                    break None;
+                }
+                Err(reqwest_eventsource::Error::StreamEnded) => {
+                    let error = ApiError::Other {
+                        status: None,
+                        message: String::from(
+                            "The connection closed before the response was complete.",
+                        ),
+                    };
+                    break Some((error, None));
+                }
+                Err(e) => {
+                    let error = ApiError::Other {
+                        status: None,
+                        message: e.to_string(),
+                    };
+                    break Some((error, None));
+                }
+                _ => {}
+            }
+        };
+        es.close();
+
+        if failed.is_some() && lines_to_move_up > 0 {
+            execute!(
+                stdout,
+                MoveToPreviousLine(lines_to_move_up),
+                Clear(ClearType::FromCursorDown),
+            )?;
+        }
+        Ok(failed)
+    }
+}
+
+/// The response of the current attempt, as far as it was streamed.
+#[derive(Default)]
+struct Streamed {
+    choices: Vec<String>,
+    /// Which choices got their `finish_reason`.
+    finished: Vec<bool>,
+    usage: Option<Usage>,
+    /// Content chunks received over all choices, roughly one token each.
+    response_tokens: usize,
+}
+
+/// How [`Request::execute`] shows the response while it arrives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Render {
+    /// Redraw all choices as tokens are streamed in.
+    Stream,
+    /// Print all choices once the response is complete.
+    Once,
+    /// Print nothing.
+    Silent,
+}
+
+#[derive(Debug)]
+pub struct Completion {
+    pub choices: Vec<String>,
+    pub usage: Usage,
+    /// Whether `usage` was estimated locally because the API did not report it.
+    pub estimated: bool,
+    /// Whether Ctrl-C stopped the response, leaving `choices` incomplete.
+    pub cancelled: bool,
+    pub model: Model,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -266,7 +550,7 @@ pub struct Delta {
     pub content: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
 pub struct Usage {
     pub prompt_tokens: usize,
     pub completion_tokens: usize,
@@ -278,3 +562,91 @@ pub fn count_token(s: &str) -> anyhow::Result<usize> {
     let tokens = bpe.encode_with_special_tokens(s);
     Ok(tokens.len())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(code: &str, message: &str) -> String {
+        serde_json::json!({
+            "error": { "message": message, "type": "invalid_request_error", "param": null, "code": code }
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_usage_chunk() {
+        let request = || Request::new(String::from("gpt-4o"), Vec::new(), 2, 1.0, 0.0);
+        let json =
+            serde_json::to_value(request().report_usage("https://api.openai.com/v1/")).unwrap();
+        assert_eq!(json["stream_options"]["include_usage"], true);
+        for api_base in ["http://localhost:8080/v1", "not a url"] {
+            let json = serde_json::to_value(request().report_usage(api_base)).unwrap();
+            assert!(json.get("stream_options").is_none());
+        }
+
+        let chunk = r#"{"id":"x","object":"chat.completion.chunk","created":0,"model":"gpt-4o","choices":[],"usage":{"prompt_tokens":120,"completion_tokens":34,"total_tokens":154}}"#;
+        let response = serde_json::from_str::<Response>(chunk).unwrap();
+        assert!(response.choices.is_empty());
+        let usage = response.usage.unwrap();
+        assert_eq!(
+            (
+                usage.prompt_tokens,
+                usage.completion_tokens,
+                usage.total_tokens
+            ),
+            (120, 34, 154)
+        );
+    }
+
+    #[test]
+    fn test_api_error() {
+        let model = "gpt-4o";
+        assert_eq!(
+            api_error(
+                Some(401),
+                &body("invalid_api_key", "Incorrect API key"),
+                model
+            ),
+            ApiError::InvalidKey
+        );
+        assert_eq!(
+            api_error(
+                Some(429),
+                &body("insufficient_quota", "Out of quota"),
+                model
+            ),
+            ApiError::QuotaExceeded
+        );
+        assert_eq!(
+            api_error(
+                Some(400),
+                &body("context_length_exceeded", "Too long"),
+                model
+            ),
+            ApiError::ContextLengthExceeded
+        );
+        assert_eq!(
+            api_error(Some(404), &body("model_not_found", "No such model"), model),
+            ApiError::ModelNotFound(String::from(model))
+        );
+        assert_eq!(
+            api_error(Some(429), "Too Many Requests", model),
+            ApiError::RateLimited
+        );
+        assert_eq!(
+            api_error(Some(500), &body("server_error", "Try again"), model),
+            ApiError::Other {
+                status: Some(500),
+                message: String::from("Try again")
+            }
+        );
+        assert_eq!(
+            api_error(Some(502), " Bad Gateway\n", model),
+            ApiError::Other {
+                status: Some(502),
+                message: String::from("Bad Gateway")
+            }
+        );
+    }
+}
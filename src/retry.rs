@@ -0,0 +1,163 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+use crate::error::ApiError;
+
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How often and how long turbocommit asks the API before it gives up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Policy {
+    pub max_attempts: u32,
+    /// For all attempts together, including the waits between them.
+    pub timeout: Duration,
+}
+
+/// Whether another attempt may succeed where this one failed.
+pub const fn is_transient(error: &ApiError) -> bool {
+    match error {
+        ApiError::RateLimited => true,
+        ApiError::Other {
+            status: Some(status),
+            ..
+        } => *status == 408 || *status >= 500,
+        ApiError::Other { status: None, .. } => true,
+        _ => false,
+    }
+}
+
+/// Whether another model may succeed where this one failed, even after retrying.
+/// A rejected key or an exhausted quota affects all models alike.
+pub const fn may_fall_back(error: &ApiError) -> bool {
+    !matches!(
+        error,
+        ApiError::InvalidKey
+            | ApiError::QuotaExceeded
+            | ApiError::Other {
+                status: Some(401),
+                ..
+            }
+    )
+}
+
+/// How long the server asks to wait before the next attempt: `retry-after-ms`, `Retry-After`
+/// in seconds, or the latest `x-ratelimit-reset-*` of a limit that has nothing remaining.
+pub fn requested_delay(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(ms) = header("retry-after-ms").and_then(|ms| ms.trim().parse::<f64>().ok()) {
+        return Some(Duration::from_secs_f64(ms.max(0.0) / 1000.0));
+    }
+    if let Some(seconds) = header(RETRY_AFTER.as_str()).and_then(|s| s.trim().parse::<u64>().ok()) {
+        return Some(Duration::from_secs(seconds));
+    }
+    ["requests", "tokens"]
+        .into_iter()
+        .filter(|limit| header(&format!("x-ratelimit-remaining-{limit}")) == Some("0"))
+        .filter_map(|limit| header(&format!("x-ratelimit-reset-{limit}")).and_then(parse_reset))
+        .max()
+}
+
+/// Parses the durations of `x-ratelimit-reset-*` headers, like `1s`, `6m0s` or `20ms`.
+/// A number without a unit is in seconds.
+fn parse_reset(value: &str) -> Option<Duration> {
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    let mut seconds = 0.0;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(number_end);
+        let unit_end = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_end);
+        let scale = match unit {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" | "" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        seconds += number.parse::<f64>().ok()? * scale;
+        rest = tail;
+    }
+    Some(Duration::from_secs_f64(seconds))
+}
+
+/// Exponential backoff for the given attempt, starting at 1, where `jitter` between 0 and 1
+/// picks a delay between half and all of it, so clients that failed together spread out.
+fn backoff(attempt: u32, jitter: f64) -> Duration {
+    let delay = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_DELAY);
+    delay.mul_f64(0.5 + jitter.clamp(0.0, 1.0) / 2.0)
+}
+
+/// How long to wait after the given attempt failed.
+pub fn delay(attempt: u32, requested: Option<Duration>) -> Duration {
+    requested.unwrap_or_else(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.subsec_nanos());
+        backoff(attempt, f64::from(nanos) / 1e9)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reset() {
+        assert_eq!(parse_reset("1s"), Some(Duration::from_secs(1)));
+        assert_eq!(parse_reset("6m0s"), Some(Duration::from_secs(360)));
+        assert_eq!(parse_reset("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_reset("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_reset("7"), Some(Duration::from_secs(7)));
+        assert_eq!(parse_reset("soon"), None);
+        assert_eq!(parse_reset(""), None);
+    }
+
+    #[test]
+    fn test_requested_delay() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining-requests", "10".parse().unwrap());
+        headers.insert("x-ratelimit-reset-requests", "1s".parse().unwrap());
+        headers.insert("x-ratelimit-remaining-tokens", "0".parse().unwrap());
+        headers.insert("x-ratelimit-reset-tokens", "6m0s".parse().unwrap());
+        assert_eq!(requested_delay(&headers), Some(Duration::from_secs(360)));
+        headers.insert(RETRY_AFTER, "20".parse().unwrap());
+        assert_eq!(requested_delay(&headers), Some(Duration::from_secs(20)));
+        headers.insert("retry-after-ms", "250".parse().unwrap());
+        assert_eq!(requested_delay(&headers), Some(Duration::from_millis(250)));
+        assert_eq!(requested_delay(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1, 1.0), Duration::from_millis(500));
+        assert_eq!(backoff(1, 0.0), Duration::from_millis(250));
+        assert_eq!(backoff(3, 1.0), Duration::from_secs(2));
+        assert_eq!(backoff(30, 1.0), MAX_DELAY);
+        assert!(is_transient(&ApiError::RateLimited));
+        assert!(!is_transient(&ApiError::QuotaExceeded));
+        assert!(is_transient(&ApiError::Other {
+            status: Some(503),
+            message: String::new()
+        }));
+        assert!(!is_transient(&ApiError::Other {
+            status: Some(400),
+            message: String::new()
+        }));
+        assert!(may_fall_back(&ApiError::ModelNotFound(String::from(
+            "gpt-5"
+        ))));
+        assert!(may_fall_back(&ApiError::ContextLengthExceeded));
+        assert!(!may_fall_back(&ApiError::QuotaExceeded));
+    }
+}
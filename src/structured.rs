@@ -0,0 +1,311 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::model::Model;
+use crate::trailers;
+
+/// A conventional commit as the API returns it in structured mode.
+/// Missing fields default, so a partial response can be shown while it streams.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Commit {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub scope: Option<String>,
+    pub breaking: bool,
+    pub subject: String,
+    pub body: Option<String>,
+    pub footers: Vec<Footer>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Footer {
+    pub token: String,
+    pub value: String,
+}
+
+impl Commit {
+    /// The canonical message: `type(scope)!: subject`, the body, then the footers
+    /// as a trailer block.
+    pub fn render(&self) -> String {
+        let mut message = self.kind.trim().to_string();
+        if let Some(scope) = self.scope.as_deref().map(str::trim) {
+            if !scope.is_empty() {
+                message.push_str(&format!("({scope})"));
+            }
+        }
+        if self.breaking {
+            message.push('!');
+        }
+        let subject = self.subject.trim();
+        if !subject.is_empty() {
+            message.push_str(": ");
+            message.push_str(subject);
+        }
+        if let Some(body) = self.body.as_deref().map(str::trim) {
+            if !body.is_empty() {
+                message.push_str("\n\n");
+                message.push_str(body);
+            }
+        }
+        let footers = self
+            .footers
+            .iter()
+            .filter(|footer| !footer.token.trim().is_empty())
+            .map(|footer| {
+                (
+                    footer.token.trim().to_string(),
+                    footer.value.trim().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        trailers::append(&message, &footers)
+    }
+}
+
+/// How a model can be asked for a [`Commit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `response_format` with a strict JSON schema.
+    Schema,
+    /// JSON mode, with the schema described in a system message.
+    JsonObject,
+}
+
+/// How `model` can be asked for a [`Commit`], or `None` if it supports neither a JSON
+/// schema nor JSON mode.
+pub const fn format(model: Model) -> Option<Format> {
+    match model {
+        Model::Gpt4o => Some(Format::Schema),
+        Model::Gpt4o20240513
+        | Model::Gpt4Turbo20240409
+        | Model::Gpt4TurboPreview
+        | Model::Gpt40125Preview
+        | Model::Gpt41106Preview
+        | Model::Gpt35Turbo
+        | Model::Gpt35Turbo1106
+        | Model::Gpt35Turbo0125 => Some(Format::JsonObject),
+        _ => None,
+    }
+}
+
+/// Fails for a model that cannot be asked for JSON, before anything is sent.
+pub fn ensure_supported(model: Model) -> anyhow::Result<()> {
+    if format(model).is_none() {
+        anyhow::bail!(
+            "{model} does not support structured output. Pick a model like gpt-4o with -m, or leave out --structured."
+        );
+    }
+    Ok(())
+}
+
+/// The `response_format` of a request in structured mode.
+pub fn response_format(format: Format) -> serde_json::Value {
+    match format {
+        Format::Schema => json!({
+            "type": "json_schema",
+            "json_schema": {
+                "name": "conventional_commit",
+                "strict": true,
+                "schema": schema(),
+            },
+        }),
+        Format::JsonObject => json!({ "type": "json_object" }),
+    }
+}
+
+/// The system message that describes the JSON to answer with in JSON mode, which does
+/// not take a schema.
+pub fn instruction() -> String {
+    format!(
+        "Answer with a JSON object of the commit message that follows this JSON schema:\n{}",
+        schema()
+    )
+}
+
+/// The JSON schema of [`Commit`].
+fn schema() -> serde_json::Value {
+    json!({
+        "type": "object",
+        "additionalProperties": false,
+        "required": ["type", "scope", "breaking", "subject", "body", "footers"],
+        "properties": {
+            "type": {
+                "type": "string",
+                "description": "The conventional commit type, like feat, fix, refactor, docs, test or chore.",
+            },
+            "scope": {
+                "type": ["string", "null"],
+                "description": "The part of the project that changed, or null.",
+            },
+            "breaking": {
+                "type": "boolean",
+                "description": "Whether the change breaks existing users.",
+            },
+            "subject": {
+                "type": "string",
+                "description": "Imperative summary of the change, lowercase, without a trailing period.",
+            },
+            "body": {
+                "type": ["string", "null"],
+                "description": "What changed and why, in plain text paragraphs, or null.",
+            },
+            "footers": {
+                "type": "array",
+                "description": "Footers like `BREAKING CHANGE: ...` or `Refs: #123`.",
+                "items": {
+                    "type": "object",
+                    "additionalProperties": false,
+                    "required": ["token", "value"],
+                    "properties": {
+                        "token": { "type": "string" },
+                        "value": { "type": "string" },
+                    },
+                },
+            },
+        },
+    })
+}
+
+/// Renders a complete response into the message. Content that is not JSON at all, like from
+/// an API that ignores `response_format`, is kept as it is.
+pub fn render_response(content: &str) -> String {
+    if !content.trim_start().starts_with('{') {
+        return content.to_string();
+    }
+    render_partial(content)
+}
+
+/// Renders as much of the streamed JSON as can be parsed so far. Cuts off what cannot be
+/// completed into valid JSON, like half a key or a literal, until the rest parses.
+pub fn render_partial(content: &str) -> String {
+    let mut end = content.len();
+    loop {
+        let prefix = &content[..end];
+        if let Some(commit) =
+            complete(prefix).and_then(|json| serde_json::from_str::<Commit>(&json).ok())
+        {
+            return commit.render();
+        }
+        match prefix.char_indices().next_back() {
+            Some((i, _)) => end = i,
+            None => return String::new(),
+        }
+    }
+}
+
+/// Closes the open string, arrays and objects of a JSON prefix. Returns `None` where a value
+/// would be missing, after a `:`.
+fn complete(partial: &str) -> Option<String> {
+    let mut closers = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in partial.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => closers.push('}'),
+            '[' => closers.push(']'),
+            '}' | ']' => {
+                closers.pop();
+            }
+            _ => {}
+        }
+    }
+
+    let mut json = if in_string {
+        format!("{partial}\"")
+    } else {
+        let trimmed = partial.trim_end();
+        if trimmed.ends_with(':') {
+            return None;
+        }
+        trimmed.trim_end_matches(',').to_string()
+    };
+    json.extend(closers.iter().rev());
+    Some(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &str = r##"{"type":"feat","scope":"cli","breaking":true,"subject":"add structured output","body":"Parse the \"commit\" as JSON.","footers":[{"token":"BREAKING CHANGE","value":"drops free text"},{"token":"Refs","value":"#42"}]}"##;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render_response(RESPONSE),
+            "feat(cli)!: add structured output\n\nParse the \"commit\" as JSON.\n\nBREAKING CHANGE: drops free text\nRefs: #42"
+        );
+        let commit = Commit {
+            kind: String::from("fix"),
+            scope: Some(String::new()),
+            subject: String::from("handle empty diffs"),
+            ..Commit::default()
+        };
+        assert_eq!(commit.render(), "fix: handle empty diffs");
+        assert_eq!(render_response("fix: plain text"), "fix: plain text");
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format(Model::Gpt4o), Some(Format::Schema));
+        assert_eq!(
+            response_format(Format::Schema)["json_schema"]["strict"],
+            true
+        );
+        for model in [
+            Model::Gpt35Turbo,
+            Model::Gpt4Turbo20240409,
+            Model::Gpt4o20240513,
+        ] {
+            assert_eq!(format(model), Some(Format::JsonObject));
+            assert!(ensure_supported(model).is_ok());
+        }
+        assert_eq!(response_format(Format::JsonObject)["type"], "json_object");
+        // JSON mode needs the word JSON in the messages, and takes the schema from there
+        assert!(instruction().contains("JSON") && instruction().contains("\"footers\""));
+        for model in [Model::Gpt4, Model::Gpt432k, Model::Gpt35TurboInstruct] {
+            assert_eq!(format(model), None);
+            assert!(ensure_supported(model)
+                .unwrap_err()
+                .to_string()
+                .starts_with(&model.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_render_partial() {
+        assert_eq!(render_partial(""), "");
+        assert_eq!(render_partial(r#"{"type":"fe"#), "fe");
+        assert_eq!(render_partial(r#"{"type":"feat","sco"#), "feat");
+        assert_eq!(render_partial(r#"{"type":"feat","scope":nu"#), "feat");
+        assert_eq!(
+            render_partial(
+                r#"{"type":"feat","scope":null,"breaking":false,"subject":"add \"x\" \u00"#
+            ),
+            "feat: add \"x\""
+        );
+        assert_eq!(
+            render_partial(
+                r##"{"type":"fix","subject":"a","footers":[{"token":"Refs","value":"#4"##
+            ),
+            "fix: a\n\nRefs: #4"
+        );
+        // every prefix renders something no longer than the full message
+        let full = render_response(RESPONSE);
+        for (i, _) in RESPONSE.char_indices() {
+            assert!(render_partial(&RESPONSE[..i]).len() <= full.len());
+        }
+    }
+}
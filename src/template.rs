@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use git2::Repository;
+
+use crate::git;
+
+/// Variables available in prompt templates, with their values.
+pub type Vars = BTreeMap<&'static str, String>;
+
+enum Node<'a> {
+    Text(&'a str),
+    Var(&'a str),
+    If {
+        var: &'a str,
+        then: Vec<Node<'a>>,
+        otherwise: Vec<Node<'a>>,
+    },
+}
+
+#[derive(PartialEq)]
+enum Stop {
+    Eof,
+    Else,
+    EndIf,
+}
+
+/// Renders `{{var}}` and `{{#if var}}...{{else}}...{{/if}}` sections, where a variable
+/// counts as set if it is not blank. A block tag swallows the line break after it,
+/// so it can stand on a line of its own.
+pub fn render(template: &str, vars: &Vars) -> anyhow::Result<String> {
+    let mut rest = template;
+    let (nodes, _) = parse(&mut rest, false)?;
+    let mut out = String::new();
+    render_nodes(&nodes, vars, &mut out)?;
+    Ok(out)
+}
+
+/// Whether `template` refers to `var` anywhere, in a `{{var}}` or a condition.
+pub fn uses(template: &str, var: &str) -> bool {
+    let mut rest = template;
+    parse(&mut rest, false).is_ok_and(|(nodes, _)| uses_nodes(&nodes, var))
+}
+
+fn uses_nodes(nodes: &[Node], name: &str) -> bool {
+    nodes.iter().any(|node| match node {
+        Node::Text(_) => false,
+        Node::Var(var) => *var == name,
+        Node::If {
+            var,
+            then,
+            otherwise,
+        } => *var == name || uses_nodes(then, name) || uses_nodes(otherwise, name),
+    })
+}
+
+fn parse<'a>(rest: &mut &'a str, nested: bool) -> anyhow::Result<(Vec<Node<'a>>, Stop)> {
+    let mut nodes = Vec::new();
+    loop {
+        let Some(start) = rest.find("{{") else {
+            if !rest.is_empty() {
+                nodes.push(Node::Text(rest));
+            }
+            *rest = "";
+            if nested {
+                anyhow::bail!("Prompt template has an `{{{{#if}}}}` without `{{{{/if}}}}`.");
+            }
+            return Ok((nodes, Stop::Eof));
+        };
+        if start > 0 {
+            nodes.push(Node::Text(&rest[..start]));
+        }
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            anyhow::bail!("Prompt template has a `{{{{` without `}}}}`.");
+        };
+        let tag = after[..end].trim();
+        *rest = &after[end + 2..];
+
+        if let Some(var) = tag.strip_prefix("#if ") {
+            skip_line_break(rest);
+            let (then, end) = parse(rest, true)?;
+            let otherwise = if end == Stop::Else {
+                let (otherwise, end) = parse(rest, true)?;
+                if end != Stop::EndIf {
+                    anyhow::bail!("Prompt template has two `{{{{else}}}}` in one `{{{{#if}}}}`.");
+                }
+                otherwise
+            } else {
+                Vec::new()
+            };
+            nodes.push(Node::If {
+                var: var.trim(),
+                then,
+                otherwise,
+            });
+        } else if tag == "else" || tag == "/if" {
+            if !nested {
+                anyhow::bail!("Prompt template has an `{{{{{tag}}}}}` without `{{{{#if}}}}`.");
+            }
+            skip_line_break(rest);
+            let end = if tag == "else" {
+                Stop::Else
+            } else {
+                Stop::EndIf
+            };
+            return Ok((nodes, end));
+        } else {
+            nodes.push(Node::Var(tag));
+        }
+    }
+}
+
+fn skip_line_break(rest: &mut &str) {
+    if let Some(stripped) = rest.strip_prefix('\n') {
+        *rest = stripped;
+    }
+}
+
+fn render_nodes(nodes: &[Node], vars: &Vars, out: &mut String) -> anyhow::Result<()> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var(var) => out.push_str(lookup(vars, var)?),
+            Node::If {
+                var,
+                then,
+                otherwise,
+            } => {
+                if lookup(vars, var)?.trim().is_empty() {
+                    render_nodes(otherwise, vars, out)?;
+                } else {
+                    render_nodes(then, vars, out)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn lookup<'v>(vars: &'v Vars, var: &str) -> anyhow::Result<&'v str> {
+    vars.get(var).map(String::as_str).ok_or_else(|| {
+        let known = vars.keys().copied().collect::<Vec<_>>().join(", ");
+        anyhow::anyhow!("Unknown prompt template variable `{var}`. Known variables are: {known}")
+    })
+}
+
+/// The template variables for a change to `files` in `repo`, with the ticket key
+/// found in the branch name.
+pub fn vars(
+    repo: Option<&Repository>,
+    ticket: Option<&str>,
+    files: &[String],
+    instruction: &str,
+) -> Vars {
+    let branch = repo.and_then(git::current_branch).unwrap_or_default();
+    let recent_commits = repo
+        .map(|repo| git::recent_summaries(repo, 5))
+        .unwrap_or_default();
+    Vars::from([
+        ("ticket", ticket.unwrap_or_default().to_string()),
+        ("branch", branch),
+        ("changed_files", files.join("\n")),
+        ("recent_commits", recent_commits.join("\n")),
+        ("language", language(files).unwrap_or_default()),
+        ("user_instruction", instruction.trim().to_string()),
+    ])
+}
+
+/// The language most of `files` are written in, judged by their extension.
+fn language(files: &[String]) -> Option<String> {
+    let mut counts = BTreeMap::new();
+    for file in files {
+        let extension = Path::new(file)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        let language = match extension {
+            "rs" => "Rust",
+            "py" => "Python",
+            "js" | "mjs" | "cjs" | "jsx" => "JavaScript",
+            "ts" | "tsx" => "TypeScript",
+            "go" => "Go",
+            "java" => "Java",
+            "kt" | "kts" => "Kotlin",
+            "swift" => "Swift",
+            "c" | "h" => "C",
+            "cc" | "cpp" | "cxx" | "hpp" => "C++",
+            "cs" => "C#",
+            "rb" => "Ruby",
+            "php" => "PHP",
+            "sh" | "bash" | "zsh" => "Shell",
+            "md" => "Markdown",
+            _ => continue,
+        };
+        *counts.entry(language).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map(|(language, _)| language.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&'static str, &str)]) -> Vars {
+        pairs
+            .iter()
+            .map(|(key, value)| (*key, value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render() {
+        let vars = vars(&[("branch", "main"), ("ticket", ""), ("language", "Rust")]);
+        assert_eq!(
+            render(
+                "On {{ branch }}.{{#if language}} Mostly {{language}}.{{/if}}",
+                &vars
+            )
+            .unwrap(),
+            "On main. Mostly Rust."
+        );
+        assert_eq!(
+            render(
+                "Start\n{{#if ticket}}\nTicket {{ticket}}\n{{else}}\nNo ticket\n{{/if}}\nEnd",
+                &vars
+            )
+            .unwrap(),
+            "Start\nNo ticket\nEnd"
+        );
+        assert_eq!(render("no variables", &vars).unwrap(), "no variables");
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let vars = vars(&[("branch", "main")]);
+        assert!(render("{{brnach}}", &vars).is_err());
+        assert!(render("{{#if branch}}open", &vars).is_err());
+        assert!(render("{{/if}}", &vars).is_err());
+        assert!(render("{{branch", &vars).is_err());
+        assert!(uses("x {{#if a}}{{branch}}{{/if}}", "branch"));
+        assert!(!uses("x {{a}}", "branch"));
+    }
+
+    #[test]
+    fn test_language() {
+        let files = ["src/main.rs", "src/cli.rs", "README.md", "Cargo.lock"].map(String::from);
+        assert_eq!(language(&files).as_deref(), Some("Rust"));
+        assert_eq!(language(&[]), None);
+    }
+}
@@ -0,0 +1,156 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::trailers;
+
+/// Where a ticket key has to appear in the commit message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Placement {
+    /// Only available to the prompt as `{{ticket}}`.
+    #[default]
+    Off,
+    /// At the start of the description, `feat: PROJ-1234 add login`.
+    Prefix,
+    /// As the scope, `feat(PROJ-1234): add login`.
+    Scope,
+    /// In a `Refs: PROJ-1234` footer.
+    Footer,
+}
+
+/// The ticket key in `branch`: the first capture group of `pattern` if it has one,
+/// otherwise the whole match.
+pub fn extract(pattern: &str, branch: &str) -> anyhow::Result<Option<String>> {
+    let regex = Regex::new(pattern)
+        .map_err(|err| anyhow::anyhow!("Invalid ticket_pattern `{pattern}`: {err}"))?;
+    Ok(regex.captures(branch).and_then(|captures| {
+        captures
+            .get(1)
+            .or_else(|| captures.get(0))
+            .map(|key| key.as_str().to_string())
+    }))
+}
+
+/// Tells the AI where to put the ticket key.
+pub fn instruction(ticket: &str, placement: Placement) -> Option<String> {
+    let example = match placement {
+        Placement::Off => return None,
+        Placement::Prefix => {
+            format!("start the description with it, like `feat: {ticket} add login`")
+        }
+        Placement::Scope => format!("use it as the scope, like `feat({ticket}): add login`"),
+        Placement::Footer => format!("end the message with the footer `Refs: {ticket}`"),
+    };
+    Some(format!(
+        "The commit belongs to the ticket {ticket}, {example}."
+    ))
+}
+
+/// Puts `ticket` into `message` as `placement` requires, unless it already is there.
+pub fn place(message: &str, ticket: &str, placement: Placement) -> String {
+    let message = message.trim();
+    let (header, body) = message.split_once('\n').unwrap_or((message, ""));
+    let header = match placement {
+        Placement::Off => return message.to_string(),
+        Placement::Footer => {
+            if message.lines().any(|line| is_refs(line, ticket)) {
+                return message.to_string();
+            }
+            return trailers::append(message, &[(String::from("Refs"), ticket.to_string())]);
+        }
+        _ if header.contains(ticket) => return message.to_string(),
+        Placement::Prefix => match header.split_once(": ") {
+            Some((prefix, description)) => format!("{prefix}: {ticket} {description}"),
+            None => format!("{ticket} {header}"),
+        },
+        Placement::Scope => match header.split_once(": ") {
+            Some((prefix, description)) => {
+                let breaking = if prefix.ends_with('!') { "!" } else { "" };
+                let kind = prefix.trim_end_matches('!');
+                let kind = kind.split_once('(').map_or(kind, |(kind, _)| kind);
+                format!("{kind}({ticket}){breaking}: {description}")
+            }
+            None => format!("{ticket} {header}"),
+        },
+    };
+    if body.is_empty() {
+        header
+    } else {
+        format!("{header}\n{body}")
+    }
+}
+
+fn is_refs(line: &str, ticket: &str) -> bool {
+    line.split_once(':').is_some_and(|(token, value)| {
+        token.trim().eq_ignore_ascii_case("refs") && value.contains(ticket)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATTERN: &str = "[A-Z][A-Z0-9]+-[0-9]+";
+
+    #[test]
+    fn test_extract() {
+        assert_eq!(
+            extract(PATTERN, "feature/PROJ-1234-add-login").unwrap(),
+            Some(String::from("PROJ-1234"))
+        );
+        assert_eq!(extract(PATTERN, "main").unwrap(), None);
+        assert_eq!(
+            extract("issue-([0-9]+)", "fix/issue-77").unwrap(),
+            Some(String::from("77"))
+        );
+        assert!(extract("(", "main").is_err());
+    }
+
+    #[test]
+    fn test_place_in_header() {
+        assert_eq!(
+            place("feat: add login\n\nBody.", "PROJ-1", Placement::Prefix),
+            "feat: PROJ-1 add login\n\nBody."
+        );
+        assert_eq!(
+            place("feat(api)!: add login", "PROJ-1", Placement::Scope),
+            "feat(PROJ-1)!: add login"
+        );
+        assert_eq!(
+            place("feat(PROJ-1): add login", "PROJ-1", Placement::Scope),
+            "feat(PROJ-1): add login"
+        );
+        assert_eq!(
+            place("add login", "PROJ-1", Placement::Scope),
+            "PROJ-1 add login"
+        );
+        assert_eq!(
+            place("feat: add login", "PROJ-1", Placement::Off),
+            "feat: add login"
+        );
+    }
+
+    #[test]
+    fn test_place_in_footer() {
+        assert_eq!(
+            place("feat: add login", "PROJ-1", Placement::Footer),
+            "feat: add login\n\nRefs: PROJ-1"
+        );
+        assert_eq!(
+            place(
+                "feat: add login\n\nBody.\n\nSigned-off-by: Me <me@example.com>",
+                "PROJ-1",
+                Placement::Footer
+            ),
+            "feat: add login\n\nBody.\n\nSigned-off-by: Me <me@example.com>\nRefs: PROJ-1"
+        );
+        assert_eq!(
+            place(
+                "feat: add login\n\nRefs: PROJ-1",
+                "PROJ-1",
+                Placement::Footer
+            ),
+            "feat: add login\n\nRefs: PROJ-1"
+        );
+    }
+}
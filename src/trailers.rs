@@ -0,0 +1,212 @@
+/// Trailers that only turbocommit adds, never the AI, since it cannot know them.
+pub const IDENTITY_TOKENS: [&str; 2] = ["Signed-off-by", "Co-authored-by"];
+
+/// Splits a `Token: value` trailer line. `BREAKING CHANGE` is the one token with a space,
+/// as conventional commits allow it.
+pub fn parse(line: &str) -> Option<(&str, &str)> {
+    let (token, value) = line.split_once(':')?;
+    let token = token.trim_end();
+    let valid = token == "BREAKING CHANGE"
+        || !token.is_empty()
+            && !token.starts_with('-')
+            && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    (valid && !value.trim().is_empty()).then(|| (token, value.trim()))
+}
+
+/// The trailer lines at the end of `message`: its last paragraph, if that is not the
+/// header and [`is_block`].
+pub fn block(message: &str) -> Option<&str> {
+    let (_, last) = message.trim_end().rsplit_once("\n\n")?;
+    is_block(last).then_some(last)
+}
+
+/// Whether every line of `paragraph` is a trailer or the indented continuation of one.
+pub fn is_block(paragraph: &str) -> bool {
+    let mut lines = paragraph.lines();
+    lines.next().is_some_and(|first| parse(first).is_some())
+        && lines.all(|line| parse(line).is_some() || line.starts_with([' ', '\t']))
+}
+
+/// Appends `trailers` to `message` like `git interpret-trailers` does: to the trailer block
+/// at the end if there is one, otherwise as a new paragraph. A trailer that is already
+/// there with the same value is not added again.
+pub fn append(message: &str, trailers: &[(String, String)]) -> String {
+    let mut message = message.trim_end().to_string();
+    let mut existing = block(&message)
+        .map(|block| {
+            block
+                .lines()
+                .filter_map(parse)
+                .map(|(token, value)| (token.to_lowercase(), value.to_string()))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let mut in_block = block(&message).is_some();
+
+    for (token, value) in trailers {
+        let key = (token.to_lowercase(), value.trim().to_string());
+        if existing.contains(&key) {
+            continue;
+        }
+        message.push_str(if in_block { "\n" } else { "\n\n" });
+        message.push_str(&format!("{token}: {}", value.trim()));
+        existing.push(key);
+        in_block = true;
+    }
+    message
+}
+
+/// Removes trailers with any of `tokens` from the trailer block of `message`.
+pub fn strip(message: &str, tokens: &[&str]) -> String {
+    let message = message.trim_end();
+    let Some(block) = block(message) else {
+        return message.to_string();
+    };
+    let head = message[..message.len() - block.len()].trim_end();
+    let mut kept = Vec::new();
+    let mut dropping = false;
+    for line in block.lines() {
+        if let Some((token, _)) = parse(line) {
+            dropping = tokens.iter().any(|strip| strip.eq_ignore_ascii_case(token));
+        }
+        if !dropping {
+            kept.push(line);
+        }
+    }
+    if kept.is_empty() {
+        head.to_string()
+    } else {
+        format!("{head}\n\n{}", kept.join("\n"))
+    }
+}
+
+/// Parses configured or given `Token: value` trailers.
+pub fn parse_all(trailers: &[String]) -> anyhow::Result<Vec<(String, String)>> {
+    trailers
+        .iter()
+        .map(|trailer| {
+            parse(trailer)
+                .map(|(token, value)| (token.to_string(), value.to_string()))
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Invalid trailer `{trailer}`, expected `Token: value`.")
+                })
+        })
+        .collect()
+}
+
+/// The one author in `authors` whose `Name <email>` contains `query`, ignoring case,
+/// or `query` itself if it is a full `Name <email>`.
+pub fn co_author(query: &str, authors: &[String]) -> anyhow::Result<String> {
+    let query = query.trim();
+    if query.contains('<') && query.ends_with('>') {
+        return Ok(query.to_string());
+    }
+    let needle = query.to_lowercase();
+    let matches = authors
+        .iter()
+        .filter(|author| author.to_lowercase().contains(&needle))
+        .collect::<Vec<_>>();
+    match matches.as_slice() {
+        [author] => Ok(author.to_string()),
+        [] => anyhow::bail!(
+            "No recent author matches `{query}`. Use `Name <email>` for someone else."
+        ),
+        _ => anyhow::bail!(
+            "Several recent authors match `{query}`: {}",
+            matches
+                .iter()
+                .map(|author| author.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trailer(token: &str, value: &str) -> (String, String) {
+        (token.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn test_append() {
+        let signoff = || trailer("Signed-off-by", "Me <me@example.com>");
+        assert_eq!(
+            append("feat: add login\n", &[signoff()]),
+            "feat: add login\n\nSigned-off-by: Me <me@example.com>"
+        );
+        assert_eq!(
+            append("feat: add login\n\nBody text.", &[signoff()]),
+            "feat: add login\n\nBody text.\n\nSigned-off-by: Me <me@example.com>"
+        );
+        assert_eq!(
+            append("feat: add login\n\nRefs: PROJ-1", &[signoff(), signoff()]),
+            "feat: add login\n\nRefs: PROJ-1\nSigned-off-by: Me <me@example.com>"
+        );
+        assert_eq!(
+            append(
+                "feat: add login\n\nsigned-off-by: Me <me@example.com>",
+                &[signoff()]
+            ),
+            "feat: add login\n\nsigned-off-by: Me <me@example.com>"
+        );
+    }
+
+    #[test]
+    fn test_body_is_not_a_trailer_block() {
+        let message = "fix: handle timeouts\n\nNote: this retries once.\nIt waits a second.";
+        assert_eq!(
+            append(message, &[trailer("Refs", "PROJ-1")]),
+            format!("{message}\n\nRefs: PROJ-1")
+        );
+    }
+
+    #[test]
+    fn test_strip() {
+        let message = "feat: add login\n\nBody.\n\nRefs: PROJ-1\nCo-authored-by: Someone\n  <someone@example.com>";
+        assert_eq!(
+            strip(message, &IDENTITY_TOKENS),
+            "feat: add login\n\nBody.\n\nRefs: PROJ-1"
+        );
+        assert_eq!(
+            strip("feat: add login\n\nSigned-off-by: Bot", &IDENTITY_TOKENS),
+            "feat: add login"
+        );
+        assert!(parse_all(&[String::from("no trailer")]).is_err());
+    }
+
+    #[test]
+    fn test_breaking_change() {
+        assert_eq!(
+            parse("BREAKING CHANGE: drops v1"),
+            Some(("BREAKING CHANGE", "drops v1"))
+        );
+        assert_eq!(parse("Some words: not a trailer"), None);
+        assert_eq!(
+            append(
+                "feat!: drop v1\n\nBREAKING CHANGE: drops v1",
+                &[trailer("Refs", "PROJ-1")]
+            ),
+            "feat!: drop v1\n\nBREAKING CHANGE: drops v1\nRefs: PROJ-1"
+        );
+    }
+
+    #[test]
+    fn test_co_author() {
+        let authors = [
+            "Ada Lovelace <ada@example.com>",
+            "Alan Turing <alan@example.com>",
+        ]
+        .map(String::from);
+        assert_eq!(co_author("ada", &authors).unwrap(), authors[0]);
+        assert_eq!(co_author("alan@", &authors).unwrap(), authors[1]);
+        assert!(co_author("a", &authors).is_err());
+        assert!(co_author("grace", &authors).is_err());
+        assert_eq!(
+            co_author("Grace Hopper <grace@example.com>", &authors).unwrap(),
+            "Grace Hopper <grace@example.com>"
+        );
+    }
+}
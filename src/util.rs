@@ -1,27 +1,63 @@
 use std::time::Duration;
 
 use colored::Colorize;
-use inquire::MultiSelect;
+use inquire::{MultiSelect, Select};
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::{config::Config, git, openai};
+use crate::error::Failure;
+use crate::model::Model;
+use crate::{git, interrupt, openai, validate};
 
+/// The first of `models`, in the configured order, that `tokens` fit into.
+pub fn fitting_model(models: &[Model], tokens: usize) -> Option<Model> {
+    models
+        .iter()
+        .copied()
+        .find(|model| tokens <= model.context_size())
+}
+
+/// Tells that `model` is used instead of `primary`, since the request is too long for it.
+pub fn report_fallback(primary: Model, model: Model) {
+    if model != primary {
+        eprintln!(
+            "{} {}",
+            format!("The request is too long for {primary}.").yellow(),
+            format!("Using {model} instead.").bright_black()
+        );
+    }
+}
+
+/// Renders `source` as a patch for the first of `models` it fits into. If it fits into
+/// none of them, it is made to fit into the one with the largest context by letting the
+/// user deselect files, or leaving out the largest ones.
 pub fn decide_diff(
-    repo: &git2::Repository,
+    source: &git2::Diff,
+    ignore: &[String],
     used_tokens: usize,
-    context: usize,
-) -> anyhow::Result<(String, usize)> {
-    let staged_files = git::staged_files(&repo)?;
-    let mut diff = git::diff(&repo, &staged_files)?;
+    models: &[Model],
+    non_interactive: bool,
+) -> anyhow::Result<(String, usize, Model)> {
+    let staged_files = git::without_ignored(git::files(source), ignore)?;
+    let mut diff = git::patch(source, &staged_files)?;
     let mut diff_tokens = openai::count_token(&diff)?;
 
     if diff_tokens == 0 {
-        println!(
-            "{} {}",
-            "No staged files.".red(),
-            "Please stage the files you want to commit.".bright_black()
-        );
-        std::process::exit(1);
+        return Err(Failure::NoStagedChanges.into());
+    }
+
+    let model = fitting_model(models, used_tokens + diff_tokens)
+        .or_else(|| models.iter().copied().max_by_key(Model::context_size))
+        .ok_or_else(|| anyhow::anyhow!("No model to use."))?;
+    report_fallback(models[0], model);
+    let context = model.context_size();
+
+    if non_interactive && used_tokens + diff_tokens > context {
+        let (diff, diff_tokens) =
+            fit_files(source, &staged_files, used_tokens, context).ok_or(Failure::TooLarge {
+                tokens: used_tokens + diff_tokens,
+                max: context,
+            })?;
+        return Ok((diff, diff_tokens, model));
     }
 
     while used_tokens + diff_tokens > context {
@@ -40,10 +76,58 @@ pub fn decide_diff(
             staged_files.clone(),
         )
         .prompt()?;
-        diff = git::diff(&repo, &selected_files)?;
+        diff = git::patch(source, &selected_files)?;
         diff_tokens = openai::count_token(&diff)?;
     }
-    Ok((diff, diff_tokens))
+    Ok((diff, diff_tokens, model))
+}
+
+/// Includes the files with the smallest diffs first, until the context is used up.
+/// Returns `None` if not even a single file fits.
+fn fit_files(
+    source: &git2::Diff,
+    files: &[String],
+    used_tokens: usize,
+    context: usize,
+) -> Option<(String, usize)> {
+    let mut sized = files
+        .iter()
+        .map(|file| {
+            let diff = git::patch(source, std::slice::from_ref(file)).ok()?;
+            Some((file.clone(), openai::count_token(&diff).ok()?))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    sized.sort_by_key(|(_, tokens)| *tokens);
+
+    let budget = context.saturating_sub(used_tokens);
+    let mut selected = Vec::new();
+    let mut skipped = Vec::new();
+    let mut total = 0;
+    for (file, tokens) in sized {
+        if total + tokens <= budget {
+            total += tokens;
+            selected.push(file);
+        } else {
+            skipped.push(file);
+        }
+    }
+
+    loop {
+        if selected.is_empty() {
+            return None;
+        }
+        let diff = git::patch(source, &selected).ok()?;
+        let diff_tokens = openai::count_token(&diff).ok()?;
+        if used_tokens + diff_tokens <= context {
+            eprintln!(
+                "{} {}",
+                "The request is too long!".yellow(),
+                format!("Leaving out: {}", skipped.join(", ")).bright_black()
+            );
+            return Some((diff, diff_tokens));
+        }
+        skipped.extend(selected.pop());
+    }
 }
 
 #[must_use]
@@ -73,27 +157,6 @@ pub fn count_lines(text: &str, max_width: usize) -> u16 {
     line_count + 1
 }
 
-pub fn check_config_age(max_age: Duration) -> bool {
-    let path = Config::path();
-    let metadata = match std::fs::metadata(&path) {
-        Ok(metadata) => metadata,
-        Err(_) => {
-            return false;
-        }
-    };
-    let last_modified = metadata.modified().unwrap();
-    let now = std::time::SystemTime::now();
-    match now.duration_since(last_modified) {
-        Ok(duration) => duration > max_age,
-        Err(_) => false,
-    }
-}
-
-pub fn is_system_prompt_same_as_default(system_msg: &str) -> bool {
-    let default = Config::default().system_msg;
-    system_msg == default
-}
-
 pub async fn check_version() {
     let client = match crates_io_api::AsyncClient::new(
         "turbocommit latest version checker",
@@ -126,9 +189,43 @@ pub async fn check_version() {
     }
 }
 
-pub fn choose_message(choices: Vec<String>) -> Option<String> {
+/// Asks which of the changed files to stage, when nothing is staged yet.
+pub fn select_files_to_stage(repo: &git2::Repository) -> anyhow::Result<Vec<String>> {
+    let changed = git::files(&git::working_tree(repo, true)?);
+    if changed.is_empty() {
+        return Err(Failure::NoStagedChanges.into());
+    }
+    let selected = MultiSelect::new(
+        &format!(
+            "Nothing is staged. Select the files to stage: {}",
+            "<ESC> to cancel".bright_black()
+        ),
+        changed,
+    )
+    .prompt_skippable()?
+    .unwrap_or_default();
+    if selected.is_empty() {
+        return Err(Failure::NoStagedChanges.into());
+    }
+    Ok(selected)
+}
+
+/// Picks the choice with the fewest validation findings, without asking.
+pub fn choose_best(choices: Vec<String>, max_header_length: Option<usize>) -> Option<String> {
+    let index = validate::best(&choices, max_header_length)?;
+    if choices.len() > 1 {
+        eprintln!(
+            "{}",
+            format!("Using choice [{}]", format!("{index}").purple()).bright_black()
+        );
+    }
+    choices.into_iter().nth(index)
+}
+
+/// Lets the user pick one of the choices. `None` if they cancel with Esc.
+pub fn choose_message(choices: Vec<String>) -> anyhow::Result<Option<String>> {
     if choices.len() == 1 {
-        return Some(choices[0].clone());
+        return Ok(Some(choices[0].clone()));
     }
     let max_index = choices.len();
     let commit_index = match inquire::CustomType::<usize>::new(&format!(
@@ -145,9 +242,133 @@ pub fn choose_message(choices: Vec<String>) -> Option<String> {
     .prompt()
     {
         Ok(index) => index,
-        Err(_) => {
-            return None;
-        }
+        Err(inquire::InquireError::OperationCanceled) => return Ok(None),
+        Err(err) => return Err(interrupt::from_prompt(err.into())),
     };
-    Some(choices[commit_index].clone())
+    Ok(Some(choices[commit_index].clone()))
+}
+
+/// The most any supported model generates for a single choice.
+const MAX_COMPLETION_TOKENS: usize = 4096;
+
+/// The cost of the prompt alone, and the most a request with `n` choices can cost.
+pub fn estimate_cost(model: Model, prompt_tokens: usize, n: usize) -> (f64, f64) {
+    let completion = MAX_COMPLETION_TOKENS.min(model.context_size().saturating_sub(prompt_tokens));
+    (
+        model.cost(prompt_tokens, 0),
+        model.cost(prompt_tokens, completion * n),
+    )
+}
+
+/// Whether `model` is served by the chat completions endpoint turbocommit sends requests to.
+fn is_chat_model(model: Model) -> bool {
+    !model.to_string().contains("instruct")
+}
+
+/// Chat models that fit the prompt and cost less than `model` at most, cheapest first.
+fn cheaper_models(model: Model, prompt_tokens: usize, n: usize) -> Vec<(Model, f64)> {
+    let (_, max) = estimate_cost(model, prompt_tokens, n);
+    let mut cheaper = Model::all()
+        .into_iter()
+        .filter(|other| is_chat_model(*other) && other.context_size() > prompt_tokens)
+        .map(|other| (other, estimate_cost(other, prompt_tokens, n).1))
+        .filter(|(_, cost)| *cost < max)
+        .collect::<Vec<_>>();
+    cheaper.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    cheaper
+}
+
+/// Above `threshold`, shows what the request will cost and asks whether to send it,
+/// switch to a cheaper model, or abort. Returns the model to use, or `None` to abort.
+pub fn confirm_cost(
+    model: Model,
+    prompt_tokens: usize,
+    n: usize,
+    threshold: Option<f64>,
+) -> anyhow::Result<Option<Model>> {
+    let (prompt, max) = estimate_cost(model, prompt_tokens, n);
+    if threshold.is_none_or(|threshold| max <= threshold) {
+        return Ok(Some(model));
+    }
+    eprintln!(
+        "{}",
+        format!(
+            "Sending {} prompt tokens to {} costs about {}, and at most {} with the completion",
+            format!("{prompt_tokens}").purple(),
+            model.to_string().purple(),
+            format!("~${prompt:0.4}").purple(),
+            format!("~${max:0.4}").purple()
+        )
+        .bright_black()
+    );
+
+    let send = format!("Send it to {model}");
+    let switch = String::from("Use a cheaper model");
+    let abort = String::from("Abort");
+    let cheaper = cheaper_models(model, prompt_tokens, n);
+    let mut options = vec![send.clone()];
+    if !cheaper.is_empty() {
+        options.push(switch.clone());
+    }
+    options.push(abort);
+    let answer = Select::new(
+        &format!("This may cost up to ~${max:0.4}. What now?"),
+        options,
+    )
+    .prompt()?;
+    if answer == send {
+        return Ok(Some(model));
+    }
+    if answer != switch {
+        return Ok(None);
+    }
+    let labels = cheaper
+        .iter()
+        .map(|(model, cost)| format!("{model} (at most ~${cost:0.4})"))
+        .collect::<Vec<_>>();
+    let picked = Select::new("Which model?", labels.clone()).prompt()?;
+    let index = labels
+        .iter()
+        .position(|label| *label == picked)
+        .unwrap_or(0);
+    Ok(Some(cheaper[index].0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fitting_model() {
+        let models = [Model::Gpt35Turbo, Model::Gpt432k, Model::Gpt4o];
+        assert_eq!(fitting_model(&models, 10_000), Some(Model::Gpt35Turbo));
+        assert_eq!(fitting_model(&models, 20_000), Some(Model::Gpt432k));
+        assert_eq!(fitting_model(&models, 40_000), Some(Model::Gpt4o));
+        assert_eq!(
+            fitting_model(&[Model::Gpt35Turbo, Model::Gpt4o, Model::Gpt432k], 20_000),
+            Some(Model::Gpt4o)
+        );
+        assert_eq!(fitting_model(&models, 200_000), None);
+        assert_eq!(fitting_model(&[], 10), None);
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        let (prompt, max) = estimate_cost(Model::Gpt432k, 20_000, 1);
+        assert!((prompt - 1.2).abs() < 1e-9);
+        assert!((max - Model::Gpt432k.cost(20_000, 4096)).abs() < 1e-9);
+        let (_, max) = estimate_cost(Model::Gpt4, 8000, 2);
+        assert!((max - Model::Gpt4.cost(8000, 384)).abs() < 1e-9);
+
+        let cheaper = cheaper_models(Model::Gpt432k, 20_000, 1);
+        assert!(!cheaper.is_empty());
+        assert!(cheaper
+            .iter()
+            .all(|(model, _)| model.context_size() > 20_000));
+        assert!(cheaper.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+        assert!(cheaper_models(Model::Gpt35Turbo0125, 1000, 1).is_empty());
+        assert!(!cheaper_models(Model::Gpt4, 1000, 1)
+            .iter()
+            .any(|(model, _)| *model == Model::Gpt35TurboInstruct));
+    }
 }
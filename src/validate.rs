@@ -0,0 +1,115 @@
+/// Problems with a generated commit message, in the order they were found.
+/// An empty list means the message looks like a well-formed conventional commit.
+/// Headers longer than `max_header_length` are a finding, unless it is `None`.
+pub fn findings(message: &str, max_header_length: Option<usize>) -> Vec<String> {
+    let mut findings = Vec::new();
+    let message = message.trim();
+    if message.is_empty() {
+        findings.push(String::from("message is empty"));
+        return findings;
+    }
+
+    let mut lines = message.lines();
+    let header = lines.next().unwrap_or_default();
+    if !is_conventional_header(header) {
+        findings.push(String::from(
+            "header is not of the form `type(scope)!: description`",
+        ));
+    }
+    let header_len = header.chars().count();
+    if let Some(max) = max_header_length.filter(|max| header_len > *max) {
+        findings.push(format!(
+            "header is {header_len} characters long, more than {max}"
+        ));
+    }
+    if lines.next().is_some_and(|line| !line.trim().is_empty()) {
+        findings.push(String::from(
+            "body is not separated from the header by a blank line",
+        ));
+    }
+    if message.contains("```") {
+        findings.push(String::from("message contains a markdown code fence"));
+    }
+    findings
+}
+
+fn is_conventional_header(header: &str) -> bool {
+    let Some((prefix, description)) = header.split_once(": ") else {
+        return false;
+    };
+    if description.trim().is_empty() {
+        return false;
+    }
+    let prefix = prefix.strip_suffix('!').unwrap_or(prefix);
+    let (kind, scope) = match prefix.split_once('(') {
+        Some((kind, rest)) => match rest.strip_suffix(')') {
+            Some(scope) => (kind, Some(scope)),
+            None => return false,
+        },
+        None => (prefix, None),
+    };
+    !kind.is_empty()
+        && kind.chars().all(|c| c.is_ascii_alphabetic())
+        && scope.is_none_or(|scope| {
+            !scope.is_empty() && !scope.contains(|c: char| c.is_whitespace() || c == ')')
+        })
+}
+
+/// Index of the choice with the fewest findings, preferring earlier choices on ties.
+pub fn best(choices: &[String], max_header_length: Option<usize>) -> Option<usize> {
+    choices
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, choice)| findings(choice, max_header_length).len())
+        .map(|(i, _)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_messages() {
+        assert!(findings("feat: add login", Some(72)).is_empty());
+        assert!(findings(
+            "fix(api)!: drop v1 endpoints\n\nThey were deprecated.",
+            Some(72)
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_invalid_messages() {
+        assert_eq!(findings("  ", Some(72)).len(), 1);
+        assert_eq!(findings("Add login", Some(72)).len(), 1);
+        assert_eq!(findings("feat(): add login", Some(72)).len(), 1);
+        assert_eq!(findings("feat: add login\nmore text", Some(72)).len(), 1);
+        assert_eq!(findings("```\nfeat: add login\n```", Some(72)).len(), 3);
+        assert_eq!(
+            findings(&format!("feat: {}", "a".repeat(80)), Some(72)).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_max_header_length() {
+        let header = format!("feat: {}", "a".repeat(80));
+        assert!(findings(&header, Some(100)).is_empty());
+        assert!(findings(&header, None).is_empty());
+        assert_eq!(
+            findings(&header, Some(50)),
+            ["header is 86 characters long, more than 50"]
+        );
+    }
+
+    #[test]
+    fn test_best_prefers_fewest_findings() {
+        let choices = vec![
+            String::from("Here is your commit message"),
+            String::from("feat: add login"),
+            String::from("fix: handle timeout"),
+        ];
+        assert_eq!(best(&choices, Some(72)), Some(1));
+        assert_eq!(best(&[], Some(72)), None);
+    }
+}
@@ -0,0 +1,90 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::process::Command;
+use std::{env, fs, thread};
+
+const PATCH: &str = "diff --git a/a.txt b/a.txt
+--- a/a.txt
++++ b/a.txt
+@@ -1 +1 @@
+-a
++b
+";
+
+/// Serves a single streamed completion with one choice per entry of `choices`, and returns
+/// the base URL to use as `api_base`.
+fn serve(choices: &'static [&'static str]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        reader
+            .by_ref()
+            .take(length)
+            .read_to_end(&mut Vec::new())
+            .unwrap();
+
+        let mut body = String::new();
+        for (index, content) in choices.iter().enumerate() {
+            let chunk = serde_json::json!({
+                "id": "x",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "gpt-3.5-turbo",
+                "choices": [{ "index": index, "delta": { "content": content }, "finish_reason": "stop" }],
+            });
+            body.push_str(&format!("data: {chunk}\n\n"));
+        }
+        body.push_str("data: [DONE]\n\n");
+        write!(
+            reader.into_inner(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+    });
+    format!("http://{address}")
+}
+
+#[test]
+fn test_stdout_is_only_the_message() {
+    let dir = env::temp_dir().join(format!("turbocommit-diff-file-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let patch = dir.join("change.patch");
+    fs::write(&patch, PATCH).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_turbocommit"))
+        .arg("--diff-file")
+        .arg(&patch)
+        .args(["-y", "-n", "2"])
+        .current_dir(&dir)
+        .env("HOME", &dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .env("OPENAI_API_KEY", "key")
+        .env("TURBOCOMMIT_API_BASE", serve(&["fix: a", "feat: b"]))
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).ok();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "fix: a\n");
+}